@@ -0,0 +1,541 @@
+//! Async variant of the driver, for executors such as Embassy.
+//!
+//! The serial halves implement `embedded_io_async::{Read, Write}` and the
+//! delay `embedded_hal_async::delay::DelayNs`, which also bounds every
+//! exchange with the device, so no `Timer` is needed. Commands are encoded by
+//! `AT_commands::encode` and the output of the device is parsed by
+//! `parser::Parser`, as in the blocking driver.
+//!
+//! It covers single connection mode (`AT+CIPMUX=0`): one connection opened
+//! with `tcp_connect` or `udp_server`, written with `send_data` and read with
+//! `read_network_data`. The server and the sockets of multiple connections
+//! mode (`tcp_server`, `open_socket`, ...) are only provided by the blocking
+//! driver, as are passive receive mode and the sender reported with
+//! `AT+CIPDINFO=1`. Data the device sends with a link ID is refused with
+//! `Error::Unsupported`.
+
+use core::future::{poll_fn, Future};
+use core::pin::{pin, Pin};
+use core::task::Poll;
+use embedded_hal_async::delay::DelayNs;
+use embedded_io_async::{Read, Write};
+use heapless::String;
+use heapless::Vec;
+use crate::commands::{AT_commands, AT_response, MAX_HOST_LEN, MAX_SEND_LEN};
+use crate::error::Error;
+use crate::parser::{self, Event, IpdHeader, Parser};
+use crate::response::{AccessPoint, Decode, NetworkInfo, Response, ScanOptions, Status, Version};
+use crate::net::Ipv4Addr;
+use crate::retry::RetryPolicy;
+use crate::socket::{Link, DEFAULT_BUFFER_LEN};
+
+/// Async ESP8266 driver, buffering up to `N` bytes of received data as the
+/// blocking driver does for each connection
+pub struct esp8266<TX, RX, DELAY, const N: usize = { DEFAULT_BUFFER_LEN }> {
+    tx: TX,
+    input: Input<RX, N>,
+    delay: DELAY,
+    timeout: Option<u32>,
+    retry_policy: RetryPolicy,
+    retry_override: Option<RetryPolicy>,
+    attempts: u8,
+    connection_status: bool,
+    got_ip: bool,
+    network: NetworkInfo,
+}
+
+// Receiving half of the serial interface and the state of the parser
+struct Input<RX, const N: usize> {
+    rx: RX,
+    buffer: [u8; 64],
+    // Position of the next byte to parse in `buffer`
+    start: usize,
+    // End of the bytes read into `buffer`
+    end: usize,
+    parser: Parser,
+    response: Response,
+    // Network data not read by `read_network_data` yet
+    link: Link<N>,
+}
+
+impl<RX, E, const N: usize> Input<RX, N>
+where
+    RX: Read<Error = E>,
+{
+    async fn read_byte(&mut self, name: &'static str) -> Result<u8, Error<E>> {
+        while self.start == self.end {
+            self.end = self
+                .rx
+                .read(&mut self.buffer)
+                .await
+                .map_err(|e| Error::Read(name, e))?;
+            self.start = 0;
+        }
+        let byte = self.buffer[self.start];
+        self.start = self.start + 1;
+        Ok(byte)
+    }
+
+    // Reads until a complete response is parsed.
+    // The payload of a `+IPD` frame is read into `data` as far as it fits,
+    // unless data received before is waiting in `link`. The rest of it is
+    // kept in `link`.
+    async fn next(&mut self, name: &'static str, data: &mut [u8]) -> Result<(AT_response, usize), Error<E>> {
+        loop {
+            let byte = self.read_byte(name).await?;
+            match self.parser.feed(byte) {
+                Some(Event::Line(line)) => {
+                    let response = parser::line_to_response(line);
+                    if response == AT_response::UNKNOWN_COMMAND {
+                        // Data returned by the command
+                        self.response.push_line(line);
+                    }
+                    return Ok((response, 0));
+                }
+                Some(Event::TruncatedLine(_)) => {
+                    self.response.set_truncated();
+                    return Ok((AT_response::UNKNOWN_COMMAND, 0));
+                }
+                Some(Event::Prompt) => return Ok((AT_response::ready_to_send, 0)),
+                // Connections of multiple connections mode are not supported
+                Some(Event::Ipd(IpdHeader { link_id: Some(_), .. })) => {
+                    self.read_link_payload(name, false).await?;
+                    return Err(Error::Unsupported("IPD"));
+                }
+                Some(Event::Ipd(IpdHeader { link_id: None, .. })) => {
+                    let len = if self.link.is_empty() {
+                        self.read_payload(name, data).await?
+                    } else {
+                        0
+                    };
+                    self.read_link_payload(name, true).await?;
+                    return Ok((AT_response::IPD, len));
+                }
+                // Passive receive mode is not supported, the data is dropped as `Data`
+                Some(Event::RecvData(_)) => {}
+                // Rest of a frame that was abandoned
+                Some(Event::Data(_)) => {}
+                None => {}
+            }
+        }
+    }
+
+    // Reads `+IPD` payload into `data` until it is full or the frame ends,
+    // the rest of the frame is left unread. Returns the number of bytes read.
+    async fn read_payload(&mut self, name: &'static str, data: &mut [u8]) -> Result<usize, Error<E>> {
+        let mut len = 0;
+        while len < data.len() && self.parser.pending() > 0 {
+            let byte = self.read_byte(name).await?;
+            if let Some(Event::Data(byte)) = self.parser.feed(byte) {
+                data[len] = byte;
+                len = len + 1;
+            }
+        }
+        Ok(len)
+    }
+
+    // Reads the rest of the `+IPD` payload into `link`, or drops it
+    async fn read_link_payload(&mut self, name: &'static str, keep: bool) -> Result<(), Error<E>> {
+        while self.parser.pending() > 0 {
+            let byte = self.read_byte(name).await?;
+            if let Some(Event::Data(byte)) = self.parser.feed(byte) {
+                if keep {
+                    self.link.push(byte);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Drops input that was read but not parsed yet
+    fn discard(&mut self) {
+        self.start = self.end;
+        self.parser.reset();
+    }
+}
+
+// Polls `future` until it completes. Returns `None` if `expired` completes first.
+async fn select<F: Future, T: Future>(future: F, mut expired: Pin<&mut T>) -> Option<F::Output> {
+    let mut future = pin!(future);
+    poll_fn(|cx| {
+        if let Poll::Ready(output) = future.as_mut().poll(cx) {
+            return Poll::Ready(Some(output));
+        }
+        if expired.as_mut().poll(cx).is_ready() {
+            return Poll::Ready(None);
+        }
+        Poll::Pending
+    })
+    .await
+}
+
+impl<TX, RX, DELAY, E> esp8266<TX, RX, DELAY>
+where
+    TX: Write<Error = E>,
+    RX: Read<Error = E>,
+    DELAY: DelayNs,
+{
+    /// Creates a new async ESP8266
+    /// # Example Embassy
+    /// ```
+    /// let (tx, rx) = uart.split();
+    /// let mut esp = ESP8266::asynch::esp8266::new(tx, rx, Delay);
+    /// esp.init().await.unwrap();
+    /// esp.join_AP("your_ssid", "your_password").await.unwrap();
+    /// ```
+    pub fn new(tx: TX, rx: RX, delay: DELAY) -> Self {
+        Self::with_buffer_len(tx, rx, delay)
+    }
+}
+
+impl<TX, RX, DELAY, E, const N: usize> esp8266<TX, RX, DELAY, N>
+where
+    TX: Write<Error = E>,
+    RX: Read<Error = E>,
+    DELAY: DelayNs,
+{
+    /// Creates a new async ESP8266 buffering up to `N` bytes of received
+    /// data instead of `socket::DEFAULT_BUFFER_LEN`
+    pub fn with_buffer_len(tx: TX, rx: RX, delay: DELAY) -> Self {
+        esp8266 {
+            tx: tx,
+            input: Input {
+                rx: rx,
+                buffer: [0u8; 64],
+                start: 0,
+                end: 0,
+                parser: Parser::new(),
+                response: Response::new(),
+                link: Link::new(),
+            },
+            delay: delay,
+            timeout: None,
+            retry_policy: RetryPolicy::default(),
+            retry_override: None,
+            attempts: 0,
+            connection_status: false,
+            got_ip: false,
+            network: NetworkInfo::default(),
+        }
+    }
+
+    /// Runs `f` with every AT command exchange bounded by `timeout_ms`
+    /// instead of the default timeout of each command. The defaults are not
+    /// restored if the returned future is dropped before it completes.
+    /// # Example
+    /// ```
+    /// esp.with_timeout(30_000, async |esp| esp.join_AP(ssid, pwd).await)
+    ///     .await
+    ///     .unwrap();
+    /// ```
+    pub async fn with_timeout<F, R>(&mut self, timeout_ms: u32, f: F) -> R
+    where
+        F: AsyncFnOnce(&mut Self) -> R,
+    {
+        let previous = self.timeout.replace(timeout_ms);
+        let result = f(self).await;
+        self.timeout = previous;
+        result
+    }
+
+    /// Sets the retry policy used for every command
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry_policy = policy;
+    }
+
+    /// Runs `f` with `policy` instead of the policy set with
+    /// `set_retry_policy`. It is not restored if the returned future is
+    /// dropped before it completes.
+    pub async fn with_retry_policy<F, R>(&mut self, policy: RetryPolicy, f: F) -> R
+    where
+        F: AsyncFnOnce(&mut Self) -> R,
+    {
+        let previous = self.retry_override.replace(policy);
+        let result = f(self).await;
+        self.retry_override = previous;
+        result
+    }
+
+    /// Number of times the last command was sent to the device
+    pub fn attempts(&self) -> u8 {
+        self.attempts
+    }
+
+    /// Checks that a device is present and turns off AT command echoing
+    pub async fn init(&mut self) -> Result<(), Error<E>> {
+        self.connection_status = false;
+        self.send(AT_commands::ATE(false)).await?;
+        self.send(AT_commands::AT).await?;
+        self.connection_status = true;
+        Ok(())
+    }
+
+    /// Joins an access point with given SSID and password
+    pub async fn join_AP(&mut self, ssid: &str, password: &str) -> Result<(), Error<E>> {
+        self.send(AT_commands::CWJAP(ssid, password)).await?;
+        self.get_IP().await?;
+        Ok(())
+    }
+
+    /// Reads the IP and MAC addresses of the Station and SoftAP interfaces
+    pub async fn get_IP(&mut self) -> Result<NetworkInfo, Error<E>> {
+        let info = self.query(AT_commands::CIFSR).await?;
+        self.network = info;
+        Ok(info)
+    }
+
+    /// IP address of the Station as of the last `get_IP`
+    pub fn ip(&self) -> Option<Ipv4Addr> {
+        self.network.station_ip
+    }
+
+    /// Addresses of the device as of the last `get_IP`
+    pub fn network_info(&self) -> &NetworkInfo {
+        &self.network
+    }
+
+    /// Opens a TCP connection to `host`, an IP address or domain name, in
+    /// single connection mode. Data is sent with `send_data` and read with
+    /// `read_network_data` until `close`.
+    ///
+    /// Returns `Error::InvalidArgument` for a `host` longer than
+    /// `commands::MAX_HOST_LEN` bytes.
+    pub async fn tcp_connect(&mut self, host: &str, port: u16) -> Result<(), Error<E>> {
+        if host.len() > MAX_HOST_LEN {
+            return Err(Error::InvalidArgument("CIPSTART"));
+        }
+        self.send(AT_commands::CIPSTART("TCP", host, port)).await
+    }
+
+    /// Creates a UDP server that listens on all incomming addresses, in
+    /// single connection mode. Datagrams are read with `read_network_data`.
+    pub async fn udp_server(&mut self, port: u16) -> Result<(), Error<E>> {
+        self.send(AT_commands::CWMODE(1)).await?;
+        self.send(AT_commands::CIPMUX(0)).await?;
+        self.send(AT_commands::CIPSTART_EXT("UDP", "0.0.0.0", port, port, 2))
+            .await
+    }
+
+    /// Closes the connection opened with `tcp_connect` or `udp_server`
+    pub async fn close(&mut self) -> Result<(), Error<E>> {
+        self.send(AT_commands::CIPCLOSE).await
+    }
+
+    /// Scans for APs, see the blocking `scan`
+    pub async fn scan(&mut self, options: &ScanOptions<'_>) -> Result<Vec<AccessPoint, 16>, Error<E>> {
+        self.send(AT_commands::CWLAPOPT(options.sort_by_rssi, options.mask))
+            .await?;
+        if options.ssid.is_none() && options.mac.is_none() && options.channel.is_none() {
+            self.send(AT_commands::CWLAP).await?;
+        } else {
+            self.send(AT_commands::CWLAP_EXT(options.ssid, options.mac, options.channel))
+                .await?;
+        }
+        Ok(AccessPoint::parse_all(&self.input.response, options.mask))
+    }
+
+    /// Returns the version of the AT firmware and SDK
+    pub async fn get_version(&mut self) -> Result<Version, Error<E>> {
+        self.query(AT_commands::GMR).await
+    }
+
+    /// Returns the connection status and the open connections
+    pub async fn get_status(&mut self) -> Result<Status, Error<E>> {
+        self.query(AT_commands::CIPSTATUS).await
+    }
+
+    /// Sends a command and decodes the lines it returned before `OK` as `T`
+    pub async fn query<T: Decode>(&mut self, cmd: AT_commands<'_>) -> Result<T, Error<E>> {
+        let name = cmd.name();
+        self.send(cmd).await?;
+        match T::decode(&self.input.response) {
+            Some(data) => Ok(data),
+            None if self.input.response.is_truncated() => Err(Error::BufferOverflow(name)),
+            None => Err(Error::Parse(name)),
+        }
+    }
+
+    /// Sends data to the network
+    pub async fn send_data(&mut self, data: &str) -> Result<(), Error<E>> {
+        self.send_bytes(data.as_bytes()).await
+    }
+
+    /// Sends `data` to the network as is, it may hold any bytes.
+    /// Returns once the device reported `SEND OK`, `SEND FAIL` is returned as
+    /// `Error::Fail`. Data longer than `MAX_SEND_LEN` is sent in chunks, see
+    /// the blocking `send_bytes`.
+    pub async fn send_bytes(&mut self, data: &[u8]) -> Result<(), Error<E>> {
+        let mut sent = 0;
+        for chunk in data.chunks(MAX_SEND_LEN) {
+            match self.send_chunk(chunk).await {
+                Ok(()) => sent = sent + chunk.len(),
+                Err(e) if sent == 0 => return Err(e),
+                Err(e) => return Err(Error::PartialSend(e.command(), sent)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits for data from the network and copies it into `buffer`, returns
+    /// the number of bytes copied. Returns `Error::Timeout` if no data arrived
+    /// within the `IPD` timeout. Data that does not fit, or arrives while a
+    /// command is sent, is kept for the next calls as by the blocking
+    /// `read_network_data`.
+    ///
+    /// Returns `Error::Unsupported` for data of a connection of multiple
+    /// connections mode, which is dropped.
+    pub async fn read_network_data(&mut self, buffer: &mut [u8]) -> Result<usize, Error<E>> {
+        let timeout = self.timeout.unwrap_or(AT_commands::IPD.timeout());
+        let mut expired = pin!(self.delay.delay_ms(timeout));
+        loop {
+            let link = &mut self.input.link;
+            if link.overflowed {
+                link.overflowed = false;
+                return Err(Error::BufferOverflow("IPD"));
+            }
+            if !link.is_empty() {
+                return Ok(link.read(buffer));
+            }
+            match select(self.input.next("IPD", buffer), expired.as_mut()).await {
+                None => {
+                    self.input.parser.reset();
+                    return Err(Error::Timeout("IPD"));
+                }
+                Some(Ok((AT_response::IPD, len))) if len > 0 => return Ok(len),
+                Some(Ok((AT_response::ready, _))) => {
+                    self.connection_status = false;
+                    self.got_ip = false;
+                    self.network = NetworkInfo::default();
+                    self.input.parser.reset();
+                    return Err(Error::Reset("IPD"));
+                }
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e),
+            }
+        }
+    }
+
+    //------------------------------------------------------------------------
+    // NON public functions
+    //------------------------------------------------------------------------
+
+    // Sends at most `MAX_SEND_LEN` bytes with one CIPSEND
+    async fn send_chunk(&mut self, data: &[u8]) -> Result<(), Error<E>> {
+        self.send(AT_commands::CIPSEND(data.len() as u16)).await?;
+        // Not resent, the device already dropped the prompt
+        let cmd = AT_commands::SEND(data);
+        self.attempts = 1;
+        self.input.response.clear();
+        match self.exchange(&cmd, data, false, AT_response::SEND_OK).await? {
+            AT_response::SEND_OK => Ok(()),
+            AT_response::FAIL => Err(Error::Fail("SEND")),
+            AT_response::ERROR => Err(Error::Error("SEND")),
+            _ => Err(Error::Busy("SEND")),
+        }
+    }
+
+    // Sends a command, resending it as the retry policy allows
+    async fn send(&mut self, cmd: AT_commands<'_>) -> Result<(), Error<E>> {
+        let name = cmd.name();
+        let mut cmd_buffer: String<128> = String::new();
+        let (expected, endChar) = cmd
+            .encode(&mut cmd_buffer)
+            .map_err(|_| Error::InvalidArgument(name))?;
+
+        let policy = self.retry_override.unwrap_or(self.retry_policy);
+        self.attempts = 0;
+        loop {
+            self.attempts = self.attempts + 1;
+            self.input.response.clear();
+            let response = self.exchange(&cmd, cmd_buffer.as_bytes(), endChar, expected).await?;
+            if response == expected || response == AT_response::ALREADY_CONNECTED {
+                return Ok(());
+            }
+            if !policy.is_retryable(response) || self.attempts >= policy.max_attempts {
+                return Err(match response {
+                    AT_response::FAIL => Error::Fail(name),
+                    AT_response::ERROR => Error::Error(name),
+                    _ => Error::Busy(name),
+                });
+            }
+            // Resend
+            self.delay.delay_ms(policy.backoff(self.attempts + 1) as u32).await;
+            // Unlike the blocking driver, input still queued in the serial
+            // interface cannot be dropped without waiting for it
+            self.input.discard();
+        }
+    }
+
+    // Writes `bytes` and reads responses until `expected` or a response
+    // ending the command is received, all within the timeout of `cmd`
+    async fn exchange(
+        &mut self,
+        cmd: &AT_commands<'_>,
+        bytes: &[u8],
+        endChar: bool,
+        expected: AT_response,
+    ) -> Result<AT_response, Error<E>> {
+        let name = cmd.name();
+        let timeout = self.timeout.unwrap_or(cmd.timeout());
+        let mut expired = pin!(self.delay.delay_ms(timeout));
+
+        let tx = &mut self.tx;
+        let write = async move {
+            tx.write_all(bytes).await?;
+            if endChar {
+                tx.write_all(b"\r\n").await?;
+            }
+            tx.flush().await
+        };
+        match select(write, expired.as_mut()).await {
+            Some(result) => result.map_err(|e| Error::Write(name, e))?,
+            None => return Err(Error::Timeout(name)),
+        }
+
+        let mut already_connected = false;
+        loop {
+            let response = match select(self.input.next(name, &mut []), expired.as_mut()).await {
+                Some(Ok((response, _))) => response,
+                // Network data that arrived in the middle of the response
+                Some(Err(Error::Parse(_)))
+                | Some(Err(Error::BufferOverflow(_)))
+                | Some(Err(Error::Unsupported(_))) => continue,
+                Some(Err(e)) => return Err(e),
+                None if already_connected => return Ok(AT_response::ALREADY_CONNECTED),
+                None => {
+                    // Anything partially received is not going to be completed
+                    self.input.parser.reset();
+                    return Err(Error::Timeout(name));
+                }
+            };
+            if already_connected && (response == AT_response::OK || response == AT_response::ERROR) {
+                // End of `ALREADY CONNECTED`, usually followed by `ERROR`
+                return Ok(AT_response::ALREADY_CONNECTED);
+            } else if response == AT_response::ALREADY_CONNECTED {
+                already_connected = true;
+            } else if response == expected
+                || response == AT_response::ERROR
+                || response == AT_response::FAIL
+                || response == AT_response::busy_p
+                || response == AT_response::busy_s
+            {
+                return Ok(response);
+            } else if response == AT_response::ready {
+                // The device restarted on its own
+                self.connection_status = false;
+                self.got_ip = false;
+                self.network = NetworkInfo::default();
+                self.input.parser.reset();
+                return Err(Error::Reset(name));
+            } else if response == AT_response::WIFI_CONNECTED {
+                self.connection_status = true;
+            } else if response == AT_response::WIFI_DISCONNECT {
+                self.connection_status = false;
+                self.got_ip = false;
+                self.network.station_ip = None;
+            } else if response == AT_response::WIFI_GOT_IP {
+                self.got_ip = true;
+            }
+        }
+    }
+}
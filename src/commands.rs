@@ -4,6 +4,14 @@
 // The default baud rate is 115200.
 // AT commands have to be capitalized, and must end with a new line (CR LF).
 
+use core::fmt::{self, Write};
+use heapless::String;
+use crate::net::{Ipv4Addr, MacAddr};
+
+/// Longest host name or IP address of a command opening a connection, so
+/// that the command fits in the command buffer of the driver
+pub const MAX_HOST_LEN: usize = 90;
+
 /// AT command types
 pub enum AT_type {
     /// ```
@@ -126,8 +134,26 @@ pub enum AT_commands<'a> {
     /// ```
     CWJAP(&'a str, &'a str),
 
-    //CWLAPOPT,
-    //CWLAP,
+    /// ```
+    /// Sets the configuration for the command AT+CWLAP
+    /// CWLAPOPT(sort, mask)
+    /// - sort:     false: The listed APs are not sorted
+    ///             true: Sorted by RSSI, strongest first
+    /// - mask:     Bits of the fields listed, see `AccessPoint::ECN` etc.
+    /// ```
+    CWLAPOPT(bool, u16),
+
+    /// ```
+    /// Lists available APs
+    /// ```
+    CWLAP,
+
+    /// ```
+    /// Lists available APs matching all the given filters
+    /// CWLAP_EXT(ssid, mac, channel)
+    /// ```
+    CWLAP_EXT(Option<&'a str>, Option<MacAddr>, Option<u8>),
+
     /// ```
     /// Disconnects from the AP
     /// ```
@@ -138,25 +164,64 @@ pub enum AT_commands<'a> {
     /// CWSAP(ssid, password, channel, encryption)
     /// - channel:      channel ID
     /// - encryption:   0: OPEN
-    ///                 1: WPA_PSK
-    ///                 2: WPA2_PSK
-    ///                 3: WPA_WPA2_PSK
+    ///                 2: WPA_PSK
+    ///                 3: WPA2_PSK
+    ///                 4: WPA_WPA2_PSK
     /// ```
     CWSAP(&'a str, &'a str, u8, u8),
 
-    //CWLIF,
     /// ```
-    /// Enables/Disables DHCP. Configuration Not Saved in the Flash
-    /// CWDHCP(mode, enable)
+    /// Configures the ESP8266 SoftAP. Configuration Not Saved in the Flash
+    /// CWSAP_EXT(ssid, password, channel, encryption, max connections, hidden)
+    /// - max connections:  Maximum number of stations connected (1~4)
+    /// - hidden:           0: The SSID is broadcast
+    ///                     1: The SSID is not broadcast
+    /// ```
+    CWSAP_EXT(&'a str, &'a str, u8, u8, u8, u8),
+
+    /// ```
+    /// Queries the current configuration of the ESP8266 SoftAP
+    /// CWSAP_QUERY
+    /// ```
+    CWSAP_QUERY,
+
+    /// ```
+    /// Lists the stations connected to the ESP8266 SoftAP, one
+    /// `<ip>,<mac>` line per station
+    /// CWLIF
+    /// ```
+    CWLIF,
+
+    /// ```
+    /// Enables/Disables DHCP, the client of the Station or the server of the SoftAP
+    /// CWDHCP(storage, mode, enable)
     /// - mode:     0: Sets ESP8266 SoftAP
     ///             1: Sets ESP8266 Station
     ///             2: Sets both SoftAP and Station
     /// - enable:   0: Disables DHCP
     ///             1: Enables DHCP
     /// ```
-    CWDHCP(u8, u8),
+    CWDHCP(Storage, u8, u8),
+
+    /// ```
+    /// Queries which interfaces use DHCP
+    /// CWDHCP_QUERY(storage)
+    /// ```
+    CWDHCP_QUERY(Storage),
+
+    /// ```
+    /// Sets the range of IP addresses leased by the DHCP server of the SoftAP
+    /// CWDHCPS(storage, range)
+    /// - range:    (lease time, start IP, end IP), `None` restores the default range
+    /// - lease time:   In minutes (1~2880)
+    /// ```
+    CWDHCPS(Storage, Option<(u16, Ipv4Addr, Ipv4Addr)>),
 
-    //CWDHCPS,
+    /// ```
+    /// Queries the range of IP addresses leased by the DHCP server of the SoftAP
+    /// CWDHCPS_QUERY(storage)
+    /// ```
+    CWDHCPS_QUERY(Storage),
     /// ```
     /// Auto-Connects to the AP or Not
     /// CWAUTOCONN(enable)
@@ -185,7 +250,12 @@ pub enum AT_commands<'a> {
 
     // TCP/IP
 
-    //CIPSTATUS,
+    /// ```
+    /// Gets the Connection Status
+    /// CIPSTATUS
+    /// ```
+    CIPSTATUS,
+
     //CIPDOMAIN,
     /// ```
     /// Establishes TCP Connection, UDP Transmission or SSL Connection
@@ -213,6 +283,16 @@ pub enum AT_commands<'a> {
     /// ```
     CIPSTART_EXT(&'a str, &'a str, u16, u16, u8),
 
+    /// ```
+    /// Establishes a connection in multiple connections mode
+    /// CIPSTART_MUX(link ID, type, remote IP, remote port)
+    /// link ID:     ID of the connection (0~4)
+    /// type:        "TCP", "UDP" or "SSL"
+    /// remote IP:   String parameter indicating the remote IP address
+    /// remote port: The remote port number
+    /// ```
+    CIPSTART_MUX(u8, &'a str, &'a str, u16),
+
     //CIPSSLSIZE,
     //CIPSSLCONF,
     /// ```
@@ -223,11 +303,38 @@ pub enum AT_commands<'a> {
     CIPSEND(u16),
 
     /// ```
-    /// Sends data
+    /// Sends length of data on a connection in multiple connections mode
+    /// CIPSEND_MUX(link ID, length)
+    /// link ID: ID of the connection (0~4)
+    /// length:  Length of data to be sent
+    /// ```
+    CIPSEND_MUX(u8, u16),
+
+    /// ```
+    /// Sends length of data to a remote of a UDP connection in single connection mode
+    /// CIPSEND_TO(length, remote IP, remote port)
+    /// length:      Length of data to be sent
+    /// remote IP:   IP address the data is sent to
+    /// remote port: Port the data is sent to
+    /// ```
+    CIPSEND_TO(u16, Ipv4Addr, u16),
+
+    /// ```
+    /// Sends length of data to a remote of a UDP connection in multiple connections mode
+    /// CIPSEND_MUX_TO(link ID, length, remote IP, remote port)
+    /// link ID:     ID of the connection (0~4)
+    /// length:      Length of data to be sent
+    /// remote IP:   IP address the data is sent to
+    /// remote port: Port the data is sent to
+    /// ```
+    CIPSEND_MUX_TO(u8, u16, Ipv4Addr, u16),
+
+    /// ```
+    /// Sends data after the `>` prompt of CIPSEND
     /// SEND(data)
-    /// data:  Data to be sent
+    /// data:  Data to be sent, any bytes
     /// ```
-    SEND(&'a str),
+    SEND(&'a [u8]),
 
     //CIPSENDEX,
     //CIPSENDBUF,
@@ -235,11 +342,18 @@ pub enum AT_commands<'a> {
     //CIPBUFSTATUS,
     //CIPCHECKSEQ,
     /// ```
-    /// Closes the TCP/UDP/SSL Connection
-    /// CIPCLOSE(link ID)
+    /// Closes the TCP/UDP/SSL Connection in single connection mode
+    /// CIPCLOSE
     /// ```
     CIPCLOSE,
 
+    /// ```
+    /// Closes a connection in multiple connections mode
+    /// CIPCLOSE_MUX(link ID)
+    /// link ID: ID of the connection (0~4), 5 closes all connections
+    /// ```
+    CIPCLOSE_MUX(u8),
+
     /// ```
     /// Gets the Local IP Address
     /// CIFSR
@@ -272,8 +386,47 @@ pub enum AT_commands<'a> {
     CIPSERVER_EXT(u8, u16),
 
     CIPSERVERMAXCONN,
-    CIPMODE,
-    SAVETRANSLINK,
+
+    /// ```
+    /// Sets the transfer mode of the connection in single connection mode
+    /// CIPMODE(mode)
+    /// mode:        0: Normal mode
+    ///              1: UART-WiFi passthrough mode, data is sent with
+    ///                 CIPSEND_PASSTHROUGH
+    /// ```
+    CIPMODE(u8),
+
+    /// ```
+    /// Starts sending everything written to the device in passthrough mode,
+    /// until +++ is written
+    /// CIPSEND_PASSTHROUGH
+    /// ```
+    CIPSEND_PASSTHROUGH,
+
+    /// ```
+    /// Stops entering passthrough mode when the device starts
+    /// SAVETRANSLINK_OFF
+    /// ```
+    SAVETRANSLINK_OFF,
+
+    /// ```
+    /// Saves a TCP connection the device opens in passthrough mode when it starts
+    /// SAVETRANSLINK_TCP(remote IP, remote port, keep alive)
+    /// remote IP:   IP address or domain name of the remote
+    /// remote port: The remote port number
+    /// keep alive:  TCP keep alive interval in seconds (0~7200), 0 disables it
+    /// ```
+    SAVETRANSLINK_TCP(&'a str, u16, u16),
+
+    /// ```
+    /// Saves a UDP connection the device opens in passthrough mode when it starts
+    /// SAVETRANSLINK_UDP(remote IP, remote port, local port)
+    /// remote IP:   IP address or domain name of the remote
+    /// remote port: The remote port number
+    /// local port:  The local port number, chosen by the device if `None`
+    /// ```
+    SAVETRANSLINK_UDP(&'a str, u16, Option<u16>),
+
     CIPSTO,
 
     /// ```
@@ -293,16 +446,465 @@ pub enum AT_commands<'a> {
     CIPDINFO(u8),
 
     IPD, // NO prefix
-    CIPRECVMODE,
-    CIPRECVDATA,
+
+    /// ```
+    /// Sets how the device delivers received data
+    /// CIPRECVMODE(mode)
+    /// mode:        0: Active mode, data is sent with +IPD as it arrives
+    ///              1: Passive mode, data is kept until read with CIPRECVDATA
+    /// ```
+    CIPRECVMODE(u8),
+
+    /// ```
+    /// Reads data kept in passive mode in single connection mode
+    /// CIPRECVDATA(length)
+    /// length:  Maximum length of data to read
+    /// ```
+    CIPRECVDATA(u16),
+
+    /// ```
+    /// Reads data kept in passive mode in multiple connections mode
+    /// CIPRECVDATA_MUX(link ID, length)
+    /// link ID: ID of the connection (0~4)
+    /// length:  Maximum length of data to read
+    /// ```
+    CIPRECVDATA_MUX(u8, u16),
+
+    /// ```
+    /// Gets the length of data kept in passive mode per connection
+    /// CIPRECVLEN
+    /// ```
     CIPRECVLEN,
     CIPSNTPCFG,
     CIPSNTPTIME,
     CIPDNS,
 }
 
+// A string parameter, written with `"`, `,` and `\` escaped by a backslash as
+// the device expects in SSIDs and passwords
+struct Escaped<'a>(&'a str);
+
+impl<'a> fmt::Display for Escaped<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for c in self.0.chars() {
+            if c == '"' || c == ',' || c == '\\' {
+                f.write_char('\\')?;
+            }
+            f.write_char(c)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> AT_commands<'a> {
+    /// Name of the command, without the `AT+` prefix and parameters.
+    /// Used to identify the failing command in errors.
+    pub fn name(&self) -> &'static str {
+        match self {
+            AT_commands::NO_COMMAND => "NO_COMMAND",
+            AT_commands::AT => "AT",
+            AT_commands::RST => "RST",
+            AT_commands::GMR => "GMR",
+            AT_commands::GSLP(..) => "GSLP",
+            AT_commands::ATE(..) => "ATE",
+            AT_commands::RESTORE => "RESTORE",
+            AT_commands::UART => "UART",
+            AT_commands::SLEEP => "SLEEP",
+            AT_commands::WAKEUPGPIO => "WAKEUPGPIO",
+            AT_commands::RFPOWER => "RFPOWER",
+            AT_commands::RFVDD => "RFVDD",
+            AT_commands::SYSRAM => "SYSRAM",
+            AT_commands::SYSADC => "SYSADC",
+            AT_commands::SYSIOSETCFG => "SYSIOSETCFG",
+            AT_commands::SYSIOGETCFG => "SYSIOGETCFG",
+            AT_commands::SYSGPIODIR => "SYSGPIODIR",
+            AT_commands::SYSGPIOWRITE => "SYSGPIOWRITE",
+            AT_commands::SYSGPIOREAD => "SYSGPIOREAD",
+            AT_commands::SYSMSG => "SYSMSG",
+            AT_commands::CWMODE(..) => "CWMODE",
+            AT_commands::CWJAP(..) => "CWJAP",
+            AT_commands::CWLAPOPT(..) => "CWLAPOPT",
+            AT_commands::CWLAP => "CWLAP",
+            AT_commands::CWLAP_EXT(..) => "CWLAP",
+            AT_commands::CWQAP => "CWQAP",
+            AT_commands::CWSAP(..) => "CWSAP",
+            AT_commands::CWSAP_EXT(..) => "CWSAP",
+            AT_commands::CWSAP_QUERY => "CWSAP",
+            AT_commands::CWLIF => "CWLIF",
+            AT_commands::CWDHCP(..) => "CWDHCP",
+            AT_commands::CWDHCP_QUERY(..) => "CWDHCP",
+            AT_commands::CWDHCPS(..) => "CWDHCPS",
+            AT_commands::CWDHCPS_QUERY(..) => "CWDHCPS",
+            AT_commands::CWAUTOCONN(..) => "CWAUTOCONN",
+            AT_commands::CWHOSTNAME(..) => "CWHOSTNAME",
+            AT_commands::CIPSTATUS => "CIPSTATUS",
+            AT_commands::CIPSTART(..) => "CIPSTART",
+            AT_commands::CIPSTART_EXT(..) => "CIPSTART",
+            AT_commands::CIPSTART_MUX(..) => "CIPSTART",
+            AT_commands::CIPSEND(..) => "CIPSEND",
+            AT_commands::CIPSEND_MUX(..) => "CIPSEND",
+            AT_commands::CIPSEND_TO(..) => "CIPSEND",
+            AT_commands::CIPSEND_MUX_TO(..) => "CIPSEND",
+            AT_commands::SEND(..) => "SEND",
+            AT_commands::CIPCLOSE => "CIPCLOSE",
+            AT_commands::CIPCLOSE_MUX(..) => "CIPCLOSE",
+            AT_commands::CIFSR => "CIFSR",
+            AT_commands::CIPMUX(..) => "CIPMUX",
+            AT_commands::CIPSERVER(..) => "CIPSERVER",
+            AT_commands::CIPSERVER_EXT(..) => "CIPSERVER",
+            AT_commands::CIPSERVERMAXCONN => "CIPSERVERMAXCONN",
+            AT_commands::CIPMODE(..) => "CIPMODE",
+            AT_commands::CIPSEND_PASSTHROUGH => "CIPSEND",
+            AT_commands::SAVETRANSLINK_OFF => "SAVETRANSLINK",
+            AT_commands::SAVETRANSLINK_TCP(..) => "SAVETRANSLINK",
+            AT_commands::SAVETRANSLINK_UDP(..) => "SAVETRANSLINK",
+            AT_commands::CIPSTO => "CIPSTO",
+            AT_commands::PING(..) => "PING",
+            AT_commands::CIUPDATE => "CIUPDATE",
+            AT_commands::CIPDINFO(..) => "CIPDINFO",
+            AT_commands::IPD => "IPD",
+            AT_commands::CIPRECVMODE(..) => "CIPRECVMODE",
+            AT_commands::CIPRECVDATA(..) => "CIPRECVDATA",
+            AT_commands::CIPRECVDATA_MUX(..) => "CIPRECVDATA",
+            AT_commands::CIPRECVLEN => "CIPRECVLEN",
+            AT_commands::CIPSNTPCFG => "CIPSNTPCFG",
+            AT_commands::CIPSNTPTIME => "CIPSNTPTIME",
+            AT_commands::CIPDNS => "CIPDNS",
+        }
+    }
+
+    /// Default time in ms to wait for the response of the command.
+    /// Can be overridden per call with `esp8266::with_timeout`.
+    pub fn timeout(&self) -> u32 {
+        match self {
+            AT_commands::AT => 1_000,
+            AT_commands::ATE(..) => 1_000,
+            AT_commands::RST => 5_000,
+            AT_commands::RESTORE => 5_000,
+            AT_commands::CWJAP(..) => 20_000,
+            AT_commands::CWLAP => 10_000,
+            AT_commands::CWLAP_EXT(..) => 10_000,
+            AT_commands::CWQAP => 5_000,
+            AT_commands::CIPSTART(..) => 10_000,
+            AT_commands::CIPSTART_EXT(..) => 10_000,
+            AT_commands::CIPSTART_MUX(..) => 10_000,
+            AT_commands::SEND(..) => 5_000,
+            AT_commands::PING(..) => 5_000,
+            AT_commands::CIUPDATE => 120_000,
+            AT_commands::IPD => 10_000,
+            _ => 2_000,
+        }
+    }
+
+    /// Writes the command as it is sent to the device into `buffer`, without
+    /// the `\r\n` ending. Returns the response the command succeeds with and
+    /// whether the `\r\n` ending is sent. The data of `SEND` is not written.
+    ///
+    /// Fails if the command does not fit in `buffer`, e.g. with a host name
+    /// longer than `MAX_HOST_LEN` or a long SSID.
+    pub fn encode(&self, buffer: &mut String<128>) -> Result<(AT_response, bool), fmt::Error> {
+        match self {
+            AT_commands::AT => {
+                write!(buffer, "AT")?;
+                Ok((AT_response::OK, true))
+            }
+            AT_commands::ATE(echo) => {
+                if *echo == true {
+                    write!(buffer, "ATE1")?;
+                } else {
+                    write!(buffer, "ATE0")?;
+                }
+                Ok((AT_response::OK, true))
+            }
+            AT_commands::RST => {
+                write!(buffer, "AT+RST")?;
+                Ok((AT_response::ready, true))
+            }
+            AT_commands::GMR => {
+                write!(buffer, "AT+GMR")?;
+                Ok((AT_response::OK, true))
+            }
+            AT_commands::CWJAP(ssid, pwd) => {
+                write!(buffer, "AT+CWJAP=\"{}\",\"{}\"", Escaped(ssid), Escaped(pwd))?;
+                Ok((AT_response::OK, true))
+            }
+            AT_commands::CWLAPOPT(sort, mask) => {
+                write!(buffer, "AT+CWLAPOPT={},{}", *sort as u8, mask)?;
+                Ok((AT_response::OK, true))
+            }
+            AT_commands::CWLAP => {
+                write!(buffer, "AT+CWLAP")?;
+                Ok((AT_response::OK, true))
+            }
+            AT_commands::CWLAP_EXT(ssid, mac, channel) => {
+                write!(buffer, "AT+CWLAP=")?;
+                if let Some(ssid) = ssid {
+                    write!(buffer, "\"{}\"", Escaped(ssid))?;
+                }
+                if mac.is_some() || channel.is_some() {
+                    write!(buffer, ",")?;
+                }
+                if let Some(mac) = mac {
+                    write!(buffer, "\"{}\"", mac)?;
+                }
+                if let Some(channel) = channel {
+                    write!(buffer, ",{}", channel)?;
+                }
+                Ok((AT_response::OK, true))
+            }
+            AT_commands::CWSAP(ssid, pwd, channel, ecn) => {
+                write!(
+                    buffer,
+                    "AT+CWSAP_CUR=\"{}\",\"{}\",{},{}",
+                    Escaped(ssid),
+                    Escaped(pwd),
+                    channel,
+                    ecn
+                )?;
+                Ok((AT_response::OK, true))
+            }
+            AT_commands::CWSAP_EXT(ssid, pwd, channel, ecn, max_conn, hidden) => {
+                write!(
+                    buffer,
+                    "AT+CWSAP_CUR=\"{}\",\"{}\",{},{},{},{}",
+                    Escaped(ssid),
+                    Escaped(pwd),
+                    channel,
+                    ecn,
+                    max_conn,
+                    hidden
+                )?;
+                Ok((AT_response::OK, true))
+            }
+            AT_commands::CWSAP_QUERY => {
+                write!(buffer, "AT+CWSAP_CUR?")?;
+                Ok((AT_response::OK, true))
+            }
+            AT_commands::CWLIF => {
+                write!(buffer, "AT+CWLIF")?;
+                Ok((AT_response::OK, true))
+            }
+            AT_commands::CWDHCP(storage, mode, enable) => {
+                write!(buffer, "AT+CWDHCP{}={},{}", storage.suffix(), mode, enable)?;
+                Ok((AT_response::OK, true))
+            }
+            AT_commands::CWDHCP_QUERY(storage) => {
+                write!(buffer, "AT+CWDHCP{}?", storage.suffix())?;
+                Ok((AT_response::OK, true))
+            }
+            AT_commands::CWDHCPS(storage, Some((lease_time, start, end))) => {
+                write!(
+                    buffer,
+                    "AT+CWDHCPS{}=1,{},\"{}\",\"{}\"",
+                    storage.suffix(),
+                    lease_time,
+                    start,
+                    end
+                )?;
+                Ok((AT_response::OK, true))
+            }
+            AT_commands::CWDHCPS(storage, None) => {
+                write!(buffer, "AT+CWDHCPS{}=0", storage.suffix())?;
+                Ok((AT_response::OK, true))
+            }
+            AT_commands::CWDHCPS_QUERY(storage) => {
+                write!(buffer, "AT+CWDHCPS{}?", storage.suffix())?;
+                Ok((AT_response::OK, true))
+            }
+            AT_commands::CWMODE(mode) => {
+                write!(buffer, "AT+CWMODE={}", mode)?;
+                Ok((AT_response::OK, true))
+            }
+            AT_commands::CIFSR => {
+                write!(buffer, "AT+CIFSR")?;
+                Ok((AT_response::OK, true))
+            }
+            AT_commands::CIPSTATUS => {
+                write!(buffer, "AT+CIPSTATUS")?;
+                Ok((AT_response::OK, true))
+            }
+            AT_commands::CIPMUX(mode) => {
+                write!(buffer, "AT+CIPMUX={}", mode)?;
+                Ok((AT_response::OK, true))
+            }
+            AT_commands::CIPSERVER(mode) => {
+                write!(buffer, "AT+CIPSERVER={}", mode)?;
+                Ok((AT_response::OK, true))
+            }
+            AT_commands::CIPSERVER_EXT(mode, port) => {
+                write!(buffer, "AT+CIPSERVER={},{}", mode, port)?;
+                Ok((AT_response::OK, true))
+            }
+            AT_commands::CIPSTART(protocol, remote_ip, remote_port) => {
+                write!(
+                    buffer,
+                    "AT+CIPSTART=\"{}\",\"{}\",{}",
+                    protocol, remote_ip, remote_port
+                )?;
+                Ok((AT_response::OK, true))
+            }
+            AT_commands::CIPSTART_EXT(
+                protocol,
+                remote_ip,
+                remote_port,
+                local_port,
+                mode,
+            ) => {
+                write!(
+                    buffer,
+                    "AT+CIPSTART=\"{}\",\"{}\",{},{},{}",
+                    protocol, remote_ip, remote_port, local_port, mode
+                )?;
+                Ok((AT_response::OK, true))
+            }
+            AT_commands::CIPSTART_MUX(link_id, protocol, remote_ip, remote_port) => {
+                write!(
+                    buffer,
+                    "AT+CIPSTART={},\"{}\",\"{}\",{}",
+                    link_id, protocol, remote_ip, remote_port
+                )?;
+                Ok((AT_response::OK, true))
+            }
+            AT_commands::CIPDINFO(mode) => {
+                write!(buffer, "AT+CIPDINFO={}", mode)?;
+                Ok((AT_response::OK, true))
+            }
+            AT_commands::CIPMODE(mode) => {
+                write!(buffer, "AT+CIPMODE={}", mode)?;
+                Ok((AT_response::OK, true))
+            }
+            AT_commands::CIPSEND_PASSTHROUGH => {
+                write!(buffer, "AT+CIPSEND")?;
+                Ok((AT_response::ready_to_send, true))
+            }
+            AT_commands::SAVETRANSLINK_OFF => {
+                write!(buffer, "AT+SAVETRANSLINK=0")?;
+                Ok((AT_response::OK, true))
+            }
+            AT_commands::SAVETRANSLINK_TCP(remote_ip, remote_port, keep_alive) => {
+                write!(
+                    buffer,
+                    "AT+SAVETRANSLINK=1,\"{}\",{},\"TCP\",{}",
+                    remote_ip, remote_port, keep_alive
+                )?;
+                Ok((AT_response::OK, true))
+            }
+            AT_commands::SAVETRANSLINK_UDP(remote_ip, remote_port, local_port) => {
+                write!(buffer, "AT+SAVETRANSLINK=1,\"{}\",{},\"UDP\"", remote_ip, remote_port)?;
+                if let Some(local_port) = local_port {
+                    write!(buffer, ",{}", local_port)?;
+                }
+                Ok((AT_response::OK, true))
+            }
+            AT_commands::CIPRECVMODE(mode) => {
+                write!(buffer, "AT+CIPRECVMODE={}", mode)?;
+                Ok((AT_response::OK, true))
+            }
+            AT_commands::CIPRECVDATA(length) => {
+                write!(buffer, "AT+CIPRECVDATA={}", length)?;
+                Ok((AT_response::OK, true))
+            }
+            AT_commands::CIPRECVDATA_MUX(link_id, length) => {
+                write!(buffer, "AT+CIPRECVDATA={},{}", link_id, length)?;
+                Ok((AT_response::OK, true))
+            }
+            AT_commands::CIPRECVLEN => {
+                write!(buffer, "AT+CIPRECVLEN?")?;
+                Ok((AT_response::OK, true))
+            }
+            AT_commands::CIPSEND(length) => {
+                write!(buffer, "AT+CIPSEND={}", length)?;
+                Ok((AT_response::ready_to_send, true))
+            }
+            AT_commands::CIPSEND_MUX(link_id, length) => {
+                write!(buffer, "AT+CIPSEND={},{}", link_id, length)?;
+                Ok((AT_response::ready_to_send, true))
+            }
+            AT_commands::CIPSEND_TO(length, remote_ip, remote_port) => {
+                write!(buffer, "AT+CIPSEND={},\"{}\",{}", length, remote_ip, remote_port)?;
+                Ok((AT_response::ready_to_send, true))
+            }
+            AT_commands::CIPSEND_MUX_TO(link_id, length, remote_ip, remote_port) => {
+                write!(
+                    buffer,
+                    "AT+CIPSEND={},{},\"{}\",{}",
+                    link_id, length, remote_ip, remote_port
+                )?;
+                Ok((AT_response::ready_to_send, true))
+            }
+            AT_commands::CIPCLOSE => {
+                write!(buffer, "AT+CIPCLOSE")?;
+                Ok((AT_response::OK, true))
+            }
+            AT_commands::CIPCLOSE_MUX(link_id) => {
+                write!(buffer, "AT+CIPCLOSE={}", link_id)?;
+                Ok((AT_response::OK, true))
+            }
+            // The data is written as is by the driver, it may not be text
+            AT_commands::SEND(_) => Ok((AT_response::SEND_OK, false)),
+            _ => {
+                write!(buffer, "commands::AT_commands::NO_COMMAND")?;
+                Ok((AT_response::UNKNOWN_COMMAND, true))
+            }
+        }
+    }
+}
+
+/// Encryption of an AP
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Encryption {
+    OPEN,
+    WEP,
+    WPA_PSK,
+    WPA2_PSK,
+    WPA_WPA2_PSK,
+    WPA2_ENTERPRISE,
+}
+
+impl Encryption {
+    /// Converts the `<ecn>` parameter of the device
+    pub fn from_ecn(ecn: u8) -> Option<Self> {
+        match ecn {
+            0 => Some(Encryption::OPEN),
+            1 => Some(Encryption::WEP),
+            2 => Some(Encryption::WPA_PSK),
+            3 => Some(Encryption::WPA2_PSK),
+            4 => Some(Encryption::WPA_WPA2_PSK),
+            5 => Some(Encryption::WPA2_ENTERPRISE),
+            _ => None,
+        }
+    }
+
+    /// The `<ecn>` parameter of the device
+    pub fn ecn(&self) -> u8 {
+        *self as u8
+    }
+}
+
+/// Where the device keeps a setting, the `_CUR` or `_DEF` variant of a command
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Storage {
+    /// In effect until the device restarts (`_CUR`)
+    Current,
+    /// Saved in flash and used from now on and after a restart (`_DEF`)
+    Flash,
+}
+
+impl Storage {
+    /// Suffix of the command name, `_CUR` or `_DEF`
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            Storage::Current => "_CUR",
+            Storage::Flash => "_DEF",
+        }
+    }
+}
+
+/// Maximum length of data sent with one `AT+CIPSEND`
+pub const MAX_SEND_LEN: usize = 2048;
+
 /// AT responses
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone, Copy, Debug)]
 pub enum AT_response {
     UNKNOWN_COMMAND,
     OK,
@@ -316,6 +918,7 @@ pub enum AT_response {
     WIFI_DISCONNECT,
     busy_s,
     busy_p,
+    SEND_OK,
     X_CONNECT,
     X_CLOSED,
     IPD,
@@ -338,6 +941,7 @@ pub fn str_to_response(response: &[u8]) -> AT_response {
         b"WIFI DISCONNECT" => return AT_response::WIFI_DISCONNECT,
         b"busy s..." => return AT_response::busy_s,
         b"busy p..." => return AT_response::busy_p,
+        b"SEND OK" => return AT_response::SEND_OK,
         b",CONNECT" => return AT_response::X_CONNECT,
         b",CLOSED" => return AT_response::X_CLOSED,
         b"+IPD" => return AT_response::IPD,
@@ -362,6 +966,7 @@ pub fn response_to_str<'a>(response: AT_response) -> &'a str {
         AT_response::WIFI_DISCONNECT => return "WIFI DISCONNECT",
         AT_response::busy_s => return "busy s...",
         AT_response::busy_p => return "busy p...",
+        AT_response::SEND_OK => return "SEND OK",
         AT_response::X_CONNECT => return ",CONNECT",
         AT_response::X_CLOSED => return ",CLOSED",
         AT_response::IPD => return "+IPD",
@@ -0,0 +1,150 @@
+//! Adapters for HALs still implementing the embedded-hal 0.2 traits.
+//!
+//! The `nb` based `serial::Read<u8>` / `serial::Write<u8>` halves and
+//! `DelayMs<u16>` are wrapped into the `embedded-io` and embedded-hal 1.0
+//! traits the driver is built on. `esp8266::new_hal02` does the wrapping.
+
+use core::fmt::Debug;
+use embedded_hal_02::blocking::delay::DelayMs;
+use embedded_hal_02::serial;
+use embedded_io::{ErrorKind, ErrorType, Read, ReadReady, Write};
+use hal::delay::DelayNs;
+use nb::block;
+use crate::esp8266;
+use crate::timer::Timer;
+
+/// Error of a wrapped embedded-hal 0.2 serial interface
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SerialError<E>(pub E);
+
+impl<E: Debug> embedded_io::Error for SerialError<E> {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Other
+    }
+}
+
+/// embedded-hal 0.2 transmitting half as `embedded_io::Write`
+pub struct Tx<T>(pub T);
+
+impl<T, E> ErrorType for Tx<T>
+where
+    T: serial::Write<u8, Error = E>,
+    E: Debug,
+{
+    type Error = SerialError<E>;
+}
+
+impl<T, E> Write for Tx<T>
+where
+    T: serial::Write<u8, Error = E>,
+    E: Debug,
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize, SerialError<E>> {
+        for byte in buf {
+            block!(self.0.write(*byte)).map_err(SerialError)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), SerialError<E>> {
+        block!(self.0.flush()).map_err(SerialError)
+    }
+}
+
+/// embedded-hal 0.2 receiving half as `embedded_io::Read` and `ReadReady`
+pub struct Rx<T> {
+    rx: T,
+    // Byte read by `read_ready`, returned by the next `read`
+    byte: Option<u8>,
+}
+
+impl<T> Rx<T> {
+    pub fn new(rx: T) -> Self {
+        Rx { rx: rx, byte: None }
+    }
+
+    /// Returns the wrapped receiving half
+    pub fn free(self) -> T {
+        self.rx
+    }
+}
+
+impl<T, E> ErrorType for Rx<T>
+where
+    T: serial::Read<u8, Error = E>,
+    E: Debug,
+{
+    type Error = SerialError<E>;
+}
+
+impl<T, E> ReadReady for Rx<T>
+where
+    T: serial::Read<u8, Error = E>,
+    E: Debug,
+{
+    fn read_ready(&mut self) -> Result<bool, SerialError<E>> {
+        if self.byte.is_none() {
+            match self.rx.read() {
+                Ok(byte) => self.byte = Some(byte),
+                Err(nb::Error::WouldBlock) => {}
+                Err(nb::Error::Other(e)) => return Err(SerialError(e)),
+            }
+        }
+        Ok(self.byte.is_some())
+    }
+}
+
+impl<T, E> Read for Rx<T>
+where
+    T: serial::Read<u8, Error = E>,
+    E: Debug,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, SerialError<E>> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        buf[0] = match self.byte.take() {
+            Some(byte) => byte,
+            None => block!(self.rx.read()).map_err(SerialError)?,
+        };
+        Ok(1)
+    }
+}
+
+/// embedded-hal 0.2 `DelayMs<u16>` as embedded-hal 1.0 `DelayNs`
+pub struct Delay<D>(pub D);
+
+impl<D: DelayMs<u16>> DelayNs for Delay<D> {
+    fn delay_ns(&mut self, ns: u32) {
+        // Rounded up to whole ms
+        self.delay_ms(ns / 1_000_000 + (ns % 1_000_000 != 0) as u32);
+    }
+
+    fn delay_ms(&mut self, mut ms: u32) {
+        while ms > u16::max_value() as u32 {
+            self.0.delay_ms(u16::max_value());
+            ms = ms - u16::max_value() as u32;
+        }
+        self.0.delay_ms(ms as u16);
+    }
+}
+
+impl<TX, RX, DELAY, TIMER, E> esp8266<Tx<TX>, Rx<RX>, Delay<DELAY>, TIMER>
+where
+    TX: serial::Write<u8, Error = E>,
+    RX: serial::Read<u8, Error = E>,
+    DELAY: DelayMs<u16>,
+    TIMER: Timer,
+    E: Debug,
+{
+    /// Creates a new ESP8266 on embedded-hal 0.2 serial halves and delay.
+    /// Serial errors are reported wrapped in `SerialError`.
+    /// # Example
+    /// ```
+    /// let (tx, rx) = esp_serial.split();
+    /// let mut esp = ESP8266::esp8266::new_hal02(tx, rx, delay, timer).unwrap();
+    /// ```
+    pub fn new_hal02(tx: TX, rx: RX, delay: DELAY, timer: TIMER) -> Result<Self, SerialError<E>> {
+        esp8266::new(Tx(tx), Rx::new(rx), Delay(delay), timer)
+    }
+}
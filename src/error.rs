@@ -0,0 +1,80 @@
+//! Errors returned by the ESP8266 driver.
+//!
+//! Every variant carries the name of the AT command that was being
+//! processed when the error occurred (see `AT_commands::name`). The name
+//! is kept instead of the `AT_commands` value, which borrows the parameters
+//! of the call, so that errors can outlive them. Commands sent with
+//! different parameters, e.g. `CIPSTART` and `CIPSTART_EXT`, share a name.
+
+use core::fmt::Debug;
+use embedded_io::ErrorKind;
+
+/// Driver error
+/// - `E` is the error type of the underlying serial interface
+#[derive(Debug, PartialEq)]
+pub enum Error<E> {
+    /// The serial interface failed while writing the command to the device
+    Write(&'static str, E),
+    /// The serial interface failed while reading the response from the device
+    Read(&'static str, E),
+    /// The device did not answer with the expected response in time
+    Timeout(&'static str),
+    /// The device answered with `ERROR`
+    Error(&'static str),
+    /// The device answered with `FAIL`
+    Fail(&'static str),
+    /// The device is busy processing a previous command (`busy p...` / `busy s...`)
+    Busy(&'static str),
+    /// The device restarted (`ready`) while processing the command.
+    /// Connections and settings not saved in flash are lost.
+    Reset(&'static str),
+    /// The response did not fit in the provided buffer
+    BufferOverflow(&'static str),
+    /// The response could not be parsed
+    Parse(&'static str),
+    /// All connections of the device are in use
+    NoFreeLink(&'static str),
+    /// The socket is not in a state allowing the operation, e.g. sending on
+    /// a socket that is not connected
+    InvalidState(&'static str),
+    /// A parameter is out of the range the device accepts, e.g. a host name
+    /// too long for the command
+    InvalidArgument(&'static str),
+    /// The device does not support the operation, e.g. IPv6 addresses
+    Unsupported(&'static str),
+    /// Data sent in several chunks was only partly sent, the chunk after the
+    /// given number of bytes failed
+    PartialSend(&'static str, usize),
+}
+
+// Errors of the `Passthrough` stream
+impl<E: Debug> embedded_io::Error for Error<E> {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Timeout(_) => ErrorKind::TimedOut,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+impl<E> Error<E> {
+    /// Name of the AT command that failed
+    pub fn command(&self) -> &'static str {
+        match self {
+            Error::Write(cmd, _) => cmd,
+            Error::Read(cmd, _) => cmd,
+            Error::Timeout(cmd) => cmd,
+            Error::Error(cmd) => cmd,
+            Error::Fail(cmd) => cmd,
+            Error::Busy(cmd) => cmd,
+            Error::Reset(cmd) => cmd,
+            Error::BufferOverflow(cmd) => cmd,
+            Error::Parse(cmd) => cmd,
+            Error::NoFreeLink(cmd) => cmd,
+            Error::InvalidState(cmd) => cmd,
+            Error::InvalidArgument(cmd) => cmd,
+            Error::Unsupported(cmd) => cmd,
+            Error::PartialSend(cmd, _) => cmd,
+        }
+    }
+}
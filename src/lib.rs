@@ -4,38 +4,124 @@
 #![allow(warnings)]
 
 extern crate embedded_hal as hal;
+#[cfg(feature = "hal-02")]
+extern crate embedded_hal_02;
+extern crate embedded_io;
 extern crate heapless;
+#[cfg(feature = "hal-02")]
 extern crate nb;
+#[cfg(feature = "mock")]
+extern crate std;
+#[cfg(feature = "embedded-nal")]
+extern crate embedded_nal;
+#[cfg(feature = "async")]
+extern crate embedded_hal_async;
+#[cfg(feature = "async")]
+extern crate embedded_io_async;
 
 use core::fmt::Write;
-use hal::blocking::delay;
-//use hal::blocking::delay::DelayMs;
-use hal::serial;
-use heapless::consts::*;
-use heapless::String;
-use nb::block;
+use embedded_io::{Read, ReadReady, Write as _};
+use hal::delay::DelayNs;
+use heapless::{Deque, String, Vec};
 
 /// Module for AT commands.
 /// Referenced by [Espressif AT send_ set](https://www.espressif.com/sites/default/files/documentation/4a-esp8266_at_instruction_set_en.pdf)
 pub mod commands;
 
-pub struct esp8266<TX, RX, DELAY> {
+/// Module for driver errors.
+pub mod error;
+
+/// Module for the timer used for command timeouts.
+pub mod timer;
+
+/// Module for the command retry policy.
+pub mod retry;
+
+/// Module for parsing the output of the device.
+pub mod parser;
+
+/// Module for the data returned by query commands.
+pub mod response;
+
+/// Module for network address types.
+pub mod net;
+
+/// Module for the connections of the device in multiple connections mode.
+pub mod socket;
+
+/// Module for the UART-WiFi passthrough mode.
+pub mod passthrough;
+
+/// Module implementing the `embedded-nal` network stack traits.
+#[cfg(feature = "embedded-nal")]
+pub mod nal;
+
+/// Module adapting embedded-hal 0.2 serial interfaces and delays.
+#[cfg(feature = "hal-02")]
+pub mod compat;
+
+/// Module with the async variant of the driver.
+#[cfg(feature = "async")]
+pub mod asynch;
+
+/// Module with a simulated device for host-side tests.
+#[cfg(feature = "mock")]
+pub mod mock;
+
+pub use error::Error;
+pub use net::{Ipv4Addr, MacAddr};
+pub use response::{
+    AccessPoint, Decode, Dhcp, DhcpRange, NetworkInfo, RecvLen, Response, ScanOptions,
+    SoftApConfig, SoftApInfo, Station, StationEvent,
+};
+pub use retry::RetryPolicy;
+pub use passthrough::{BootPassthrough, Passthrough, TransLink};
+pub use socket::Socket;
+pub use timer::Timer;
+
+pub struct esp8266<TX, RX, DELAY, TIMER, const N: usize = { socket::DEFAULT_BUFFER_LEN }> {
     tx: TX,
     rx: RX,
     delay: DELAY,
+    timer: TIMER,
+    timeout: Option<u32>,
+    retry_policy: RetryPolicy,
+    retry_override: Option<RetryPolicy>,
+    attempts: u8,
+    parser: parser::Parser,
+    response: Response,
     received: [u8; 32], // TODO: Max return length from ESP
     connection_status: bool,
     got_ip: bool,
-    ip: (u8, u8, u8, u8),
+    network: NetworkInfo,
+    mux: bool,
+    links: [socket::Link<N>; socket::MAX_LINKS],
+    // `+IPD` reports the sender (`AT+CIPDINFO=1`)
+    remote_info: bool,
+    // Passive receive mode (`AT+CIPRECVMODE=1`)
+    passive: bool,
+    // Link the `+CIPRECVDATA` response being read belongs to
+    recv_link: usize,
+    // Everything written is sent on the connection, until `+++`
+    passthrough: bool,
+    boot_passthrough: BootPassthrough,
+    // Stations joining or leaving the SoftAP, not read by `station_event` yet
+    station_events: Deque<StationEvent, 8>,
 }
 
-impl<TX, RX, DELAY, E> esp8266<TX, RX, DELAY>
+impl<TX, RX, DELAY, TIMER, E> esp8266<TX, RX, DELAY, TIMER>
 where
-    TX: serial::Write<u8, Error = E>,
-    RX: serial::Read<u8, Error = E>,
-    DELAY: delay::DelayMs<u16>,
+    TX: embedded_io::Write<Error = E>,
+    RX: Read<Error = E> + ReadReady,
+    DELAY: DelayNs,
+    TIMER: Timer,
 {
-    /// Creates a new ESP8266
+    /// Creates a new ESP8266 on `embedded-io` serial halves and an
+    /// embedded-hal 1.0 delay. `ReadReady` lets the driver wait for bytes
+    /// without blocking, so that it can check the timer.
+    ///
+    /// HALs still implementing the embedded-hal 0.2 traits can use
+    /// `esp8266::new_hal02` with the `hal-02` feature.
     /// # Example STM32F411
     /// ```
     /// #![no_std]
@@ -54,6 +140,7 @@ where
     ///     let rcc = dp.RCC.constrain();
     ///     let gpioa = dp.GPIOA.split();
     ///     let mut delay = Delay::new(cp.SYST, clocks);
+    ///     let timer = SysTimer::new(&clock); // Any type implementing ESP8266::Timer
     ///
     ///     let tx1 = gpioa.pa2.into_alternate_af7();
     ///     let rx1 = gpioa.pa3.into_alternate_af7();
@@ -61,50 +148,134 @@ where
     ///     let esp_serial = Serial::usart2(dp.USART2, (tx2, rx2), esp_config, clocks).unwrap();
     ///
     ///     let (tx, rx) = esp_serial.split();
-    ///     let mut esp = ESP8266::esp8266::new(usart_tx, usart_rx, delay).unwrap();
+    ///     let mut esp = ESP8266::esp8266::new(usart_tx, usart_rx, delay, timer).unwrap();
     ///     
     ///     loop {}
     /// }
     /// ```
-    pub fn new(tx: TX, rx: RX, delay: DELAY) -> Result<Self, E> {
+    pub fn new(tx: TX, rx: RX, delay: DELAY, timer: TIMER) -> Result<Self, E> {
+        Self::with_buffer_len(tx, rx, delay, timer)
+    }
+}
+
+impl<TX, RX, DELAY, TIMER, E, const N: usize> esp8266<TX, RX, DELAY, TIMER, N>
+where
+    TX: embedded_io::Write<Error = E>,
+    RX: Read<Error = E> + ReadReady,
+    DELAY: DelayNs,
+    TIMER: Timer,
+{
+    /// Creates a new ESP8266 buffering up to `N` bytes of received data per
+    /// connection instead of `socket::DEFAULT_BUFFER_LEN`, see `socket`
+    /// # Example
+    /// ```
+    /// // Keeps whole frames of the largest size the device sends
+    /// let mut esp: esp8266<_, _, _, _, { parser::MAX_IPD_LEN }> =
+    ///     esp8266::with_buffer_len(tx, rx, delay, timer).unwrap();
+    /// ```
+    pub fn with_buffer_len(tx: TX, rx: RX, delay: DELAY, timer: TIMER) -> Result<Self, E> {
         let esp8266 = esp8266 {
             tx: tx,
             rx: rx,
             delay: delay,
+            timer: timer,
+            timeout: None,
+            retry_policy: RetryPolicy::default(),
+            retry_override: None,
+            attempts: 0,
+            parser: parser::Parser::new(),
+            response: Response::new(),
             received: [0u8; 32], // TODO: Max return length from ESP
             connection_status: false,
             got_ip: false,
-            ip: (0, 0, 0, 0),
+            network: NetworkInfo::default(),
+            mux: false,
+            links: [
+                socket::Link::new(),
+                socket::Link::new(),
+                socket::Link::new(),
+                socket::Link::new(),
+                socket::Link::new(),
+            ],
+            remote_info: false,
+            passive: false,
+            recv_link: 0,
+            passthrough: false,
+            boot_passthrough: BootPassthrough::default(),
+            station_events: Deque::new(),
         };
         Ok(esp8266)
     }
 
+    /// Runs `f` with every AT command exchange bounded by `timeout_ms`
+    /// instead of the default timeout of each command
+    /// # Example
+    /// ```
+    /// // Some access points take longer than the default 20 s to join
+    /// esp.with_timeout(30_000, |esp| esp.join_AP(ssid, pwd)).unwrap();
+    /// ```
+    pub fn with_timeout<F, R>(&mut self, timeout_ms: u32, f: F) -> R
+    where
+        F: FnOnce(&mut Self) -> R,
+    {
+        let previous = self.timeout.replace(timeout_ms);
+        let result = f(self);
+        self.timeout = previous;
+        result
+    }
+
+    /// Sets the retry policy used for every command
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry_policy = policy;
+    }
+
+    /// Runs `f` with `policy` instead of the retry policy of the driver
+    /// # Example
+    /// ```
+    /// // Fail right away instead of resending
+    /// esp.with_retry_policy(RetryPolicy::never(), |esp| esp.tcp_server(80)).unwrap();
+    /// ```
+    pub fn with_retry_policy<F, R>(&mut self, policy: RetryPolicy, f: F) -> R
+    where
+        F: FnOnce(&mut Self) -> R,
+    {
+        let previous = self.retry_override.replace(policy);
+        let result = f(self);
+        self.retry_override = previous;
+        result
+    }
+
+    /// Number of times the last command was sent to the device.
+    ///
+    /// Methods sending several commands, e.g. `join_AP` which reads the IP
+    /// address after joining, report the command that failed or, if all of
+    /// them succeeded, the last one they sent.
+    pub fn attempts(&self) -> u8 {
+        self.attempts
+    }
+
     /// Initializing the connection to a connected ESP device by
     /// checking if there is a device present and turn off AT send_ echoing
-    pub fn init(&mut self) -> Result<(), ()> {
-        // Switch echoing off
-        match self.send(commands::AT_commands::ATE(false)) {
-            Ok(_) => {
-                self.connection_status = true;
-            }
-            Err(_) => self.connection_status = false,
-        }
-
-        match self.send(commands::AT_commands::AT) {
-            Ok(_) => {
-                self.connection_status = true;
-            }
-            Err(_) => self.connection_status = false,
+    ///
+    /// A device that started in passthrough mode (see `save_trans_link`)
+    /// sends commands on its connection as data. Unless `set_boot_passthrough`
+    /// is left at `BootPassthrough::Ignore`, the `+++` escape is written
+    /// first, which takes about a second, and passthrough mode is handled as
+    /// set there.
+    pub fn init(&mut self) -> Result<(), Error<E>> {
+        self.connection_status = false;
+        if self.boot_passthrough != BootPassthrough::Ignore {
+            self.escape()?;
         }
+        // Switch echoing off
+        self.send(commands::AT_commands::ATE(false))?;
+        self.recover_boot_passthrough()?;
+        self.send(commands::AT_commands::AT)?;
 
         // TODO: SHOULD PROBABLY RESET THE DEVICE
 
-        // Return
-        if !self.connection_status {
-            Err(())
-        } else {
-            Ok(())
-        }
+        self.connection_status = true;
+        Ok(())
     }
     /// Join an access point with given SSID and password
     /// # Example
@@ -113,75 +284,68 @@ where
     /// let pwd = "your_password";
     /// esp.join_AP(ssid, pwd).unwrap();
     /// ```
-    pub fn join_AP(&mut self, ssid: &str, password: &str) -> Result<(), ()> {
-        match self.send(commands::AT_commands::CWJAP(ssid, password)) {
-            Ok(_) => {
-                self.connection_status = true;
-            }
-            Err(_) => self.connection_status = false,
-        }
+    pub fn join_AP(&mut self, ssid: &str, password: &str) -> Result<(), Error<E>> {
+        self.send(commands::AT_commands::CWJAP(ssid, password))?;
         // Get the IP of the module
-        match self.send(commands::AT_commands::CIFSR) {
-            Ok(_) => {
-                self.connection_status = true;
-            }
-            Err(_) => self.connection_status = false,
-        }
-        // Return
-        if !self.connection_status {
-            Err(())
-        } else {
-            Ok(())
-        }
+        self.get_IP()?;
+        Ok(())
     }
 
-    pub fn get_IP(&mut self) -> Result<(), ()> {
-        match self.send(commands::AT_commands::CIFSR) {
-            Ok(_) => {
-                self.connection_status = true;
-            }
-            Err(_) => self.connection_status = false,
-        }
-        // Return
-        if !self.connection_status {
-            Err(())
-        } else {
-            Ok(())
-        }
+    /// Reads the IP and MAC addresses of the Station and SoftAP interfaces
+    /// and stores them in the driver, see `ip` and `network_info`
+    /// # Example
+    /// ```
+    /// let info = esp.get_IP().unwrap();
+    /// if let Some(ip) = info.station_ip {
+    ///     // ip.octets() == [192, 168, 1, 5]
+    /// }
+    /// ```
+    pub fn get_IP(&mut self) -> Result<NetworkInfo, Error<E>> {
+        let info = self.query(commands::AT_commands::CIFSR)?;
+        self.network = info;
+        Ok(info)
     }
 
-    /// Creates a TCP server for multiple connections
-    pub fn tcp_server(&mut self, port: u16) -> Result<(), ()> {
-        match self.send(commands::AT_commands::CWMODE(1)) {
-            Ok(_) => {
-                self.connection_status = true;
-            }
-            Err(_) => self.connection_status = false,
-        }
+    /// IP address of the Station as of the last `get_IP`
+    pub fn ip(&self) -> Option<Ipv4Addr> {
+        self.network.station_ip
+    }
 
-        match self.send(commands::AT_commands::CIPMUX(1)) {
-            Ok(_) => {
-                self.connection_status = true;
-            }
-            Err(_) => self.connection_status = false,
-        }
+    /// Addresses of the device as of the last `get_IP`
+    pub fn network_info(&self) -> &NetworkInfo {
+        &self.network
+    }
 
-        match self.send(commands::AT_commands::CIPSERVER_EXT(1, port)) {
-            Ok(_) => {
-                self.connection_status = true;
-            }
-            Err(_) => self.connection_status = false,
-        }
-        // Return
-        if !self.connection_status {
-            Err(())
-        } else {
-            Ok(())
-        }
+    /// Creates a TCP server for multiple connections. The connections it
+    /// accepts are taken with `accept_socket`, data of connections not taken
+    /// is returned by `read_network_data`.
+    /// # Example
+    /// ```
+    /// esp.tcp_server(80).unwrap();
+    /// let client = esp.accept_socket().unwrap();
+    /// let len = esp.read_socket(&client, &mut buffer).unwrap();
+    /// esp.send_socket(&client, "HTTP/1.0 200 OK\r\n\r\n").unwrap();
+    /// esp.close_socket(client).unwrap();
+    /// ```
+    pub fn tcp_server(&mut self, port: u16) -> Result<(), Error<E>> {
+        self.send(commands::AT_commands::CWMODE(1))?;
+        self.send(commands::AT_commands::CIPMUX(1))?;
+        self.mux = true;
+        self.send(commands::AT_commands::CIPSERVER_EXT(1, port))?;
+        Ok(())
     }
 
-    /// Creates a UDP server that listens on all incomming addresses
-    pub fn udp_server(&mut self, port: u16) -> Result<(), ()> {
+    /// Creates a UDP server that listens on all incomming addresses.
+    /// Datagrams are read with `read_network_data` or `recv_from`, the
+    /// returned `Socket` answers them with `send_to`.
+    /// # Example
+    /// ```
+    /// let server = esp.udp_server(5000).unwrap();
+    /// esp.set_remote_info(true).unwrap();
+    /// let (len, ip, port) = esp.recv_from(&mut buffer).unwrap();
+    /// esp.send_to(&server, ip, port, &buffer[..len]).unwrap();
+    /// ```
+    pub fn udp_server(&mut self, port: u16) -> Result<Socket, Error<E>> {
         /* match self.send(commands::AT_commands::CIPSERVER(0)) {
             Ok(_) => {
                 self.connection_status = true;
@@ -196,88 +360,574 @@ where
             Err(_) => self.connection_status = false,
         } */
 
-        match self.send(commands::AT_commands::CWMODE(1)) {
-            Ok(_) => {
-                self.connection_status = true;
-            }
-            Err(_) => self.connection_status = false,
+        self.send(commands::AT_commands::CWMODE(1))?;
+        self.send(commands::AT_commands::CIPMUX(0))?;
+        self.mux = false;
+        // Datagrams of different senders are told apart by `recv_from`
+        self.links[0].datagram = true;
+        self.send(commands::AT_commands::CIPSTART_EXT(
+            "UDP", "0.0.0.0", port, port, 2,
+        ))?;
+        // Not marked as used, its data is read without link
+        Ok(Socket::new(0))
+    }
+
+    /// Scans for APs. Returns as many as fit in the response buffer,
+    /// use `ScanOptions::mask` and the filters to make room for more.
+    /// # Example
+    /// ```
+    /// let options = ScanOptions {
+    ///     sort_by_rssi: true,
+    ///     channel: Some(6),
+    ///     ..ScanOptions::default()
+    /// };
+    /// for ap in esp.scan(&options).unwrap() {
+    ///     // ap.ssid, ap.rssi, ...
+    /// }
+    /// ```
+    pub fn scan(&mut self, options: &ScanOptions) -> Result<Vec<AccessPoint, 16>, Error<E>> {
+        self.send(commands::AT_commands::CWLAPOPT(options.sort_by_rssi, options.mask))?;
+        if options.ssid.is_none() && options.mac.is_none() && options.channel.is_none() {
+            self.send(commands::AT_commands::CWLAP)?;
+        } else {
+            self.send(commands::AT_commands::CWLAP_EXT(
+                options.ssid,
+                options.mac,
+                options.channel,
+            ))?;
         }
 
-        match self.send(commands::AT_commands::CIPMUX(0)) {
-            Ok(_) => {
-                self.connection_status = true;
-            }
-            Err(_) => self.connection_status = false,
+        Ok(AccessPoint::parse_all(&self.response, options.mask))
+    }
+
+    /// Starts the SoftAP of the device with `config`, in SoftAP mode or in
+    /// SoftAP+Station mode with `config.station`. The configuration is not
+    /// saved in flash.
+    ///
+    /// Returns `Error::InvalidArgument` without sending anything if the
+    /// device would refuse `config`, see `SoftApConfig::is_valid`.
+    /// # Example
+    /// ```
+    /// esp.start_soft_ap(&SoftApConfig {
+    ///     ssid: "sensor-setup",
+    ///     password: "12345678",
+    ///     channel: 6,
+    ///     ..SoftApConfig::default()
+    /// })
+    /// .unwrap();
+    /// ```
+    pub fn start_soft_ap(&mut self, config: &SoftApConfig) -> Result<(), Error<E>> {
+        if !config.is_valid() {
+            return Err(Error::InvalidArgument("CWSAP"));
         }
+        let mode = if config.station { 3 } else { 2 };
+        self.send(commands::AT_commands::CWMODE(mode))?;
+        // Not used without encryption, the device refuses most passwords then
+        let password = if config.encryption == commands::Encryption::OPEN {
+            ""
+        } else {
+            config.password
+        };
+        self.send(commands::AT_commands::CWSAP_EXT(
+            config.ssid,
+            password,
+            config.channel,
+            config.encryption.ecn(),
+            config.max_connections,
+            config.hidden as u8,
+        ))
+    }
 
-        match self.send(commands::AT_commands::CIPSTART_EXT(
-            "UDP", "0.0.0.0", port, port, 2,
-        )) {
-            Ok(_) => {
-                self.connection_status = true;
+    /// Returns the current configuration of the SoftAP
+    pub fn soft_ap_config(&mut self) -> Result<SoftApInfo, Error<E>> {
+        self.query(commands::AT_commands::CWSAP_QUERY)
+    }
+
+    /// Returns the stations connected to the SoftAP
+    pub fn stations(&mut self) -> Result<Vec<Station, 8>, Error<E>> {
+        self.send(commands::AT_commands::CWLIF)?;
+
+        let mut stations = Vec::new();
+        for line in self.response.lines() {
+            if let Some(station) = Station::parse(line) {
+                if stations.push(station).is_err() {
+                    break;
+                }
             }
-            Err(_) => self.connection_status = false,
         }
+        Ok(stations)
+    }
 
-        match self.send(commands::AT_commands::CIPSEND(4)) {
-            Ok(_) => {
-                self.connection_status = true;
-            }
-            Err(_) => self.connection_status = false,
+    /// Returns the next station joining or leaving the SoftAP.
+    ///
+    /// Events reported while other commands run are kept, the oldest is
+    /// dropped when more than 8 are pending. Without a pending event, the
+    /// output of the device is handled until a response or network data is
+    /// received or the `IPD` timeout expires, `None` if no event came with it.
+    /// # Example
+    /// ```
+    /// loop {
+    ///     match esp.station_event().unwrap() {
+    ///         Some(StationEvent::GotIp(mac, ip)) => { /* ... */ }
+    ///         Some(StationEvent::Disconnected(mac)) => { /* ... */ }
+    ///         _ => {}
+    ///     }
+    /// }
+    /// ```
+    pub fn station_event(&mut self) -> Result<Option<StationEvent>, Error<E>> {
+        if let Some(event) = self.station_events.pop_front() {
+            return Ok(Some(event));
+        }
+        match self.poll() {
+            Err(Error::Timeout(_)) => {}
+            result => result?,
         }
+        Ok(self.station_events.pop_front())
+    }
 
-        /* match self.send(commands::AT_commands::SEND("TEST")) {
-            Ok(_) => {
-                self.connection_status = true;
-            }
-            Err(_) => self.connection_status = false,
-        } */
-        // Return
-        if !self.connection_status {
-            Err(())
+    /// Enables or disables the DHCP client of the Station and the DHCP server
+    /// of the SoftAP, for the current session or saved in flash.
+    ///
+    /// Disabling the DHCP client is needed for a static IP address, the
+    /// device enables it again when it joins an AP.
+    pub fn set_dhcp(&mut self, dhcp: &Dhcp, storage: commands::Storage) -> Result<(), Error<E>> {
+        if dhcp.station == dhcp.soft_ap {
+            self.send(commands::AT_commands::CWDHCP(storage, 2, dhcp.station as u8))
         } else {
-            Ok(())
+            self.send(commands::AT_commands::CWDHCP(storage, 0, dhcp.soft_ap as u8))?;
+            self.send(commands::AT_commands::CWDHCP(storage, 1, dhcp.station as u8))
+        }
+    }
+
+    /// Returns the interfaces using DHCP, for the current session or as
+    /// saved in flash
+    pub fn dhcp(&mut self, storage: commands::Storage) -> Result<Dhcp, Error<E>> {
+        self.query(commands::AT_commands::CWDHCP_QUERY(storage))
+    }
+
+    /// Sets the IP addresses leased by the DHCP server of the SoftAP and the
+    /// lease time, for the current session or saved in flash. `None`
+    /// restores the default range of the device.
+    ///
+    /// The DHCP server must be enabled and the range must be in the subnet
+    /// of the SoftAP, otherwise the device answers with `ERROR`. Returns
+    /// `Error::InvalidArgument` without sending anything if the lease time
+    /// is out of range or the range is empty.
+    /// # Example
+    /// ```
+    /// let range = DhcpRange {
+    ///     lease_time: 60,
+    ///     start: Ipv4Addr::new(192, 168, 4, 10),
+    ///     end: Ipv4Addr::new(192, 168, 4, 20),
+    /// };
+    /// esp.set_dhcp_range(Some(&range), Storage::Flash).unwrap();
+    /// ```
+    pub fn set_dhcp_range(
+        &mut self,
+        range: Option<&DhcpRange>,
+        storage: commands::Storage,
+    ) -> Result<(), Error<E>> {
+        let range = match range {
+            Some(range) if !range.is_valid() => return Err(Error::InvalidArgument("CWDHCPS")),
+            Some(range) => Some((range.lease_time, range.start, range.end)),
+            None => None,
+        };
+        self.send(commands::AT_commands::CWDHCPS(storage, range))
+    }
+
+    /// Returns the IP addresses leased by the DHCP server of the SoftAP, for
+    /// the current session or as saved in flash
+    pub fn dhcp_range(&mut self, storage: commands::Storage) -> Result<DhcpRange, Error<E>> {
+        self.query(commands::AT_commands::CWDHCPS_QUERY(storage))
+    }
+
+    /// Returns the version of the AT firmware and SDK
+    pub fn get_version(&mut self) -> Result<response::Version, Error<E>> {
+        self.query(commands::AT_commands::GMR)
+    }
+
+    /// Returns the connection status and the open connections
+    pub fn get_status(&mut self) -> Result<response::Status, Error<E>> {
+        self.query(commands::AT_commands::CIPSTATUS)
+    }
+
+    /// Sends a command and decodes the lines it returned before `OK` as `T`
+    /// # Example
+    /// ```
+    /// let version: Version = esp.query(AT_commands::GMR).unwrap();
+    /// ```
+    pub fn query<T: Decode>(&mut self, cmd: commands::AT_commands) -> Result<T, Error<E>> {
+        let name = cmd.name();
+        self.send(cmd)?;
+        match T::decode(&self.response) {
+            Some(data) => Ok(data),
+            None if self.response.is_truncated() => Err(Error::BufferOverflow(name)),
+            None => Err(Error::Parse(name)),
         }
     }
 
     // TODO: Check for HOW we are connected to the network (CIPSERVER / TCP / UDP etc.)
     /// Sends data to the network
-    pub fn send_data(&mut self, data: &str) -> Result<(), ()> {
-        let mut chk;
-        let len = data.len() as u16;
-        match self.send(commands::AT_commands::CIPSEND(len)) {
-            Ok(_) => {
-                chk = true;
+    pub fn send_data(&mut self, data: &str) -> Result<(), Error<E>> {
+        self.send_bytes(data.as_bytes())
+    }
+
+    /// Sends `data` to the network as is, it may hold any bytes.
+    /// Waits for the `>` prompt, writes `data` without line ending and
+    /// returns once the device reported `SEND OK`. `SEND FAIL` is returned as
+    /// `Error::Fail`.
+    ///
+    /// Data longer than `commands::MAX_SEND_LEN` is sent in chunks, each one
+    /// after the previous one was reported as sent. If a chunk after the
+    /// first one fails, `Error::PartialSend` tells how many bytes were sent.
+    /// # Example
+    /// ```
+    /// esp.send_bytes(&[0xa1, 0x00, 0x0d, 0x0a]).unwrap();
+    /// ```
+    pub fn send_bytes(&mut self, data: &[u8]) -> Result<(), Error<E>> {
+        self.send_chunks(None, data)
+    }
+
+    /// Waits for data from the network and copies it into `buffer`, returns
+    /// the number of bytes copied. Returns `Error::Timeout` if no data arrived
+    /// within the `IPD` timeout.
+    ///
+    /// In single connection mode the data of a `+IPD` frame (up to
+    /// `parser::MAX_IPD_LEN` bytes) is read from the device straight into
+    /// `buffer`. What does not fit, and data received while a command was
+    /// sent, is kept in the driver (see `socket`) and returned first by the
+    /// next calls. Returns `Error::BufferOverflow` once if some of it was
+    /// dropped because that buffer was full.
+    ///
+    /// In multiple connections mode data is kept per connection. Only data of
+    /// connections accepted by `tcp_server` but not taken with
+    /// `accept_socket` is returned, connections with a `Socket` are read
+    /// with `read_socket`. In single connection mode, returns
+    /// `Error::InvalidState` while the connection belongs to the `Socket` of
+    /// `tcp_connect`.
+    pub fn read_network_data(&mut self, buffer: &mut [u8]) -> Result<usize, Error<E>> {
+        self.read_network_data_from(buffer).map(|(len, _)| len)
+    }
+
+    // `read_network_data`, also returns the sender of the data if reported
+    fn read_network_data_from(
+        &mut self,
+        buffer: &mut [u8],
+    ) -> Result<(usize, Option<(Ipv4Addr, u16)>), Error<E>> {
+        // The data is kept for `read_socket`
+        if !self.mux && self.links[0].used {
+            return Err(Error::InvalidState("IPD"));
+        }
+        self.start_timer(&commands::AT_commands::IPD);
+        loop {
+            // Data kept in the driver, of the only connection in single
+            // connection mode or of connections without `Socket`
+            if let Some(link) = self
+                .links
+                .iter_mut()
+                .find(|link| !link.used && (link.overflowed || !link.is_empty()))
+            {
+                if link.overflowed {
+                    link.overflowed = false;
+                    return Err(Error::BufferOverflow("IPD"));
+                }
+                return Ok(link.read_from(buffer));
+            }
+            let (cmd, len) = self.get_response("IPD", buffer)?;
+            if cmd == commands::AT_response::IPD && !self.mux && len > 0 {
+                return Ok((len, self.links[0].remote));
+            } else if cmd == commands::AT_response::ready {
+                self.device_reset();
+                return Err(Error::Reset("IPD"));
             }
-            Err(_) => chk = false,
         }
+    }
 
-        match self.send(commands::AT_commands::SEND(data)) {
-            Ok(_) => {
-                chk = true;
+    /// Returns a `Socket` for the next connection accepted by the server of
+    /// `tcp_server`, waiting up to the `IPD` timeout for one. Data received
+    /// on the connection before is kept for `read_socket`.
+    ///
+    /// Returns `Error::InvalidState` in single connection mode, where there
+    /// is no server.
+    pub fn accept_socket(&mut self) -> Result<Socket, Error<E>> {
+        if !self.mux {
+            return Err(Error::InvalidState("CIPSERVER"));
+        }
+        self.start_timer(&commands::AT_commands::IPD);
+        loop {
+            if let Some(link_id) = self.links.iter().position(|link| link.connected && !link.used) {
+                self.links[link_id].used = true;
+                return Ok(Socket::new(link_id as u8));
+            }
+            match self.get_response("IPD", &mut []) {
+                Ok((commands::AT_response::ready, _)) => {
+                    self.device_reset();
+                    return Err(Error::Reset("IPD"));
+                }
+                Ok(_) | Err(Error::BufferOverflow(_)) => {}
+                Err(e) => return Err(e),
             }
-            Err(_) => chk = false,
         }
+    }
+
+    /// Makes the device report the IP address and port of the sender of
+    /// received data (`AT+CIPDINFO`), needed by `recv_from`
+    pub fn set_remote_info(&mut self, enable: bool) -> Result<(), Error<E>> {
+        self.send(commands::AT_commands::CIPDINFO(enable as u8))?;
+        self.remote_info = enable;
+        Ok(())
+    }
+
+    /// Like `read_network_data`, also returns the IP address and port the
+    /// data was sent from, e.g. to answer datagrams received by `udp_server`.
+    /// The data returned by one call comes from one `+IPD` frame of a UDP
+    /// connection.
+    ///
+    /// Returns `Error::InvalidState` unless `set_remote_info(true)` was
+    /// called, and `Error::Parse` for data the device received before.
+    /// # Example
+    /// ```
+    /// esp.udp_server(5000).unwrap();
+    /// esp.set_remote_info(true).unwrap();
+    /// let (len, ip, port) = esp.recv_from(&mut buffer).unwrap();
+    /// ```
+    pub fn recv_from(&mut self, buffer: &mut [u8]) -> Result<(usize, Ipv4Addr, u16), Error<E>> {
+        if !self.remote_info {
+            return Err(Error::InvalidState("CIPDINFO"));
+        }
+        match self.read_network_data_from(buffer)? {
+            (len, Some((ip, port))) => Ok((len, ip, port)),
+            (_, None) => Err(Error::Parse("IPD")),
+        }
+    }
+
+    /// Opens a TCP connection to `host`, an IP address or domain name, in
+    /// the current connection mode: the only connection in single connection
+    /// mode, one of `socket::MAX_LINKS` in multiple connections mode.
+    /// The returned `Socket` is used with `send_socket`, `read_socket` and
+    /// `close_socket` in both modes.
+    ///
+    /// Returns `Error::InvalidArgument` for a `host` longer than
+    /// `commands::MAX_HOST_LEN` bytes.
+    /// # Example
+    /// ```
+    /// let socket = esp.tcp_connect("example.com", 80).unwrap();
+    /// esp.send_socket(&socket, "GET / HTTP/1.0\r\n\r\n").unwrap();
+    /// ```
+    pub fn tcp_connect(&mut self, host: &str, port: u16) -> Result<Socket, Error<E>> {
+        if host.len() > commands::MAX_HOST_LEN {
+            return Err(Error::InvalidArgument("CIPSTART"));
+        }
+        if self.mux {
+            return self.open_socket("TCP", host, port);
+        }
+        if self.links[0].used || self.links[0].connected {
+            return Err(Error::NoFreeLink("CIPSTART"));
+        }
+        self.send(commands::AT_commands::CIPSTART("TCP", host, port))?;
+        let link = &mut self.links[0];
+        link.used = true;
+        link.connected = true;
+        link.datagram = false;
+        Ok(Socket::new(0))
+    }
+
+    /// Opens a connection in multiple connections mode, which is switched
+    /// on if needed. Up to `socket::MAX_LINKS` connections can be open.
+    /// # Example
+    /// ```
+    /// let socket = esp.open_socket("TCP", "192.168.1.10", 8080).unwrap();
+    /// esp.send_socket(&socket, "hello").unwrap();
+    /// let mut buffer = [0u8; 64];
+    /// let len = esp.read_socket(&socket, &mut buffer).unwrap();
+    /// esp.close_socket(socket).unwrap();
+    /// ```
+    pub fn open_socket(
+        &mut self,
+        protocol: &str,
+        remote_ip: &str,
+        remote_port: u16,
+    ) -> Result<Socket, Error<E>> {
+        if remote_ip.len() > commands::MAX_HOST_LEN {
+            return Err(Error::InvalidArgument("CIPSTART"));
+        }
+        if !self.mux {
+            self.send(commands::AT_commands::CIPMUX(1))?;
+            self.mux = true;
+        }
+        // Connections accepted by a server also use up links
+        let link_id = match self.links.iter().position(|link| !link.used && !link.connected) {
+            Some(link_id) => link_id,
+            None => return Err(Error::NoFreeLink("CIPSTART")),
+        };
+        // Drop data left over from a connection accepted by a server
+        self.links[link_id].reset();
+        self.links[link_id].datagram = protocol == "UDP";
+        self.send(commands::AT_commands::CIPSTART_MUX(
+            link_id as u8,
+            protocol,
+            remote_ip,
+            remote_port,
+        ))?;
+        let link = &mut self.links[link_id];
+        link.used = true;
+        link.connected = true;
+        Ok(Socket::new(link_id as u8))
+    }
+
+    /// Sends data on a connection opened with `open_socket` or `tcp_connect`,
+    /// returns once the device reported `SEND OK`
+    pub fn send_socket(&mut self, socket: &Socket, data: &str) -> Result<(), Error<E>> {
+        self.send_socket_bytes(socket, data.as_bytes())
+    }
+
+    /// Sends `data` on a connection as is, it may hold any bytes.
+    /// See `send_bytes`.
+    pub fn send_socket_bytes(&mut self, socket: &Socket, data: &[u8]) -> Result<(), Error<E>> {
+        self.send_chunks(Some(socket), data)
+    }
 
-        // Return
-        if !chk {
-            Err(())
+    /// Sends `data` as one datagram to `remote_ip`:`remote_port` on a UDP
+    /// connection whose remote may change, i.e. opened by `udp_server`.
+    /// Datagrams longer than `commands::MAX_SEND_LEN` are not supported.
+    pub fn send_to(
+        &mut self,
+        socket: &Socket,
+        remote_ip: Ipv4Addr,
+        remote_port: u16,
+        data: &[u8],
+    ) -> Result<(), Error<E>> {
+        // Would be split into several datagrams
+        if data.len() > commands::MAX_SEND_LEN {
+            return Err(Error::Unsupported("SEND"));
+        }
+        let len = data.len() as u16;
+        if self.mux {
+            self.send(commands::AT_commands::CIPSEND_MUX_TO(
+                socket.link_id(),
+                len,
+                remote_ip,
+                remote_port,
+            ))?;
         } else {
-            Ok(())
+            self.send(commands::AT_commands::CIPSEND_TO(len, remote_ip, remote_port))?;
         }
+        self.send_payload(data)
     }
 
-    pub fn read_network_data(&mut self, mut buffer: &mut [u8]) -> Result<u8, ()> {
-        let mut found_data: bool = false;
-        let mut data_len: u8 = 0;
-        while !found_data {
-            let (cmd, len) = self.get_response(&mut buffer).unwrap();
-            if cmd == commands::AT_response::IPD {
-                found_data = true;
-                data_len = len;
+    /// Copies data received on the connection into `buffer`, waiting for
+    /// data if none was received yet. Returns the number of bytes copied,
+    /// 0 once the connection is closed and all data was read.
+    ///
+    /// Returns `Error::BufferOverflow` once if data was dropped because the
+    /// buffer of the connection was full, the data that fit is read next.
+    ///
+    /// In passive receive mode (see `set_passive_receive`) the data the
+    /// device keeps is pulled with `AT+CIPRECVDATA`, as much as fits in the
+    /// buffer of the connection. If there is none, waits up to the `IPD`
+    /// timeout for the device to report new data.
+    pub fn read_socket(&mut self, socket: &Socket, buffer: &mut [u8]) -> Result<usize, Error<E>> {
+        self.read_socket_from(socket, buffer).map(|(len, _)| len)
+    }
+
+    /// Like `read_socket`, also returns the IP address and port the data was
+    /// sent from. The data returned by one call comes from one datagram of a
+    /// UDP connection.
+    ///
+    /// Returns `Error::InvalidState` unless `set_remote_info(true)` was
+    /// called, and `Error::Parse` for data the device received before.
+    /// # Example
+    /// ```
+    /// esp.set_remote_info(true).unwrap();
+    /// let socket = esp.open_socket("UDP", "192.168.1.10", 5000).unwrap();
+    /// let (len, ip, port) = esp.recv_from_socket(&socket, &mut buffer).unwrap();
+    /// ```
+    pub fn recv_from_socket(
+        &mut self,
+        socket: &Socket,
+        buffer: &mut [u8],
+    ) -> Result<(usize, Ipv4Addr, u16), Error<E>> {
+        if !self.remote_info {
+            return Err(Error::InvalidState("CIPDINFO"));
+        }
+        match self.read_socket_from(socket, buffer)? {
+            (len, Some((ip, port))) => Ok((len, ip, port)),
+            (_, None) => Err(Error::Parse("IPD")),
+        }
+    }
+
+    // `read_socket`, also returns the sender of the data if reported
+    fn read_socket_from(
+        &mut self,
+        socket: &Socket,
+        buffer: &mut [u8],
+    ) -> Result<(usize, Option<(Ipv4Addr, u16)>), Error<E>> {
+        let link_id = socket.link_id() as usize;
+        self.start_timer(&commands::AT_commands::IPD);
+        loop {
+            if self.links[link_id].overflowed {
+                self.links[link_id].overflowed = false;
+                return Err(Error::BufferOverflow("IPD"));
+            }
+            if !self.links[link_id].is_empty() || !self.links[link_id].connected {
+                return Ok(self.links[link_id].read_from(buffer));
+            }
+            if self.passive {
+                if self.pull_data(link_id)? {
+                    continue;
+                }
+                // The commands used their own timeouts
+                self.start_timer(&commands::AT_commands::IPD);
+            }
+            match self.get_response("IPD", &mut []) {
+                Ok((commands::AT_response::ready, _)) => {
+                    self.device_reset();
+                    return Err(Error::Reset("IPD"));
+                }
+                // Data without link ID does not belong to any socket
+                Ok(_) | Err(Error::BufferOverflow(_)) => {}
+                Err(e) => return Err(e),
             }
         }
-        Ok(data_len)
+    }
+
+    /// Switches between active receive mode, where the device sends data as
+    /// soon as it arrives, and passive receive mode, where it keeps the data
+    /// until `read_socket` asks for it (`AT+CIPRECVMODE`). Passive mode keeps
+    /// the device from sending more data than the MCU can take.
+    ///
+    /// In passive mode data is only read with `read_socket`, also for the
+    /// connection of `tcp_connect` or `udp_server` in single connection mode.
+    pub fn set_passive_receive(&mut self, enable: bool) -> Result<(), Error<E>> {
+        self.send(commands::AT_commands::CIPRECVMODE(enable as u8))?;
+        self.passive = enable;
+        Ok(())
+    }
+
+    /// Returns the number of bytes the device keeps per connection in
+    /// passive receive mode (`AT+CIPRECVLEN?`)
+    pub fn recv_len(&mut self) -> Result<RecvLen, Error<E>> {
+        self.query(commands::AT_commands::CIPRECVLEN)
+    }
+
+    /// Closes a connection opened with `open_socket` or `tcp_connect`.
+    /// Data not read yet is dropped.
+    pub fn close_socket(&mut self, socket: Socket) -> Result<(), Error<E>> {
+        let link_id = socket.link_id() as usize;
+        let result = if !self.links[link_id].connected {
+            Ok(())
+        } else if self.mux {
+            self.send(commands::AT_commands::CIPCLOSE_MUX(socket.link_id()))
+        } else {
+            self.send(commands::AT_commands::CIPCLOSE)
+        };
+        self.links[link_id].reset();
+        result
+    }
+
+    /// Returns `true` until the connection is closed by either side
+    pub fn is_connected(&self, socket: &Socket) -> bool {
+        self.links[socket.link_id() as usize].connected
     }
 
     //------------------------------------------------------------------------
@@ -285,258 +935,373 @@ where
     //------------------------------------------------------------------------
 
     // Handels the sending of a specific function
-    fn send(&mut self, mut cmd: commands::AT_commands) -> Result<(), ()> {
-        self.send_command(&cmd);
-        Ok(())
+    fn send(&mut self, mut cmd: commands::AT_commands) -> Result<(), Error<E>> {
+        self.send_command(&cmd)
     }
 
     // Handles transporting the send_ to the module, and verifying the response from the module.
-    fn send_command(&mut self, cmd: &commands::AT_commands) {
-        let mut cmd_buffer: String<U64> = String::new();
-        let mut expected_buffer: String<U64> = String::new();
-        // reset buffers
-        cmd_buffer.clear();
-        expected_buffer.clear();
-
-        let (send_, expected, endChar) = match cmd {
-            commands::AT_commands::AT => ("AT", commands::AT_response::OK, true),
-            commands::AT_commands::ATE(echo) => {
-                if *echo == true {
-                    ("ATE1", commands::AT_response::OK, true)
-                } else {
-                    ("ATE0", commands::AT_response::OK, true)
-                }
-            }
-            commands::AT_commands::RST => ("AT+RST", commands::AT_response::ready, true),
-            commands::AT_commands::CWJAP(ssid, pwd) => {
-                write!(cmd_buffer, "AT+CWJAP=\"{}\",\"{}\"", ssid, pwd).unwrap();
-                (cmd_buffer.as_str(), commands::AT_response::OK, true)
-            }
-            commands::AT_commands::CWMODE(mode) => {
-                write!(cmd_buffer, "AT+CWMODE={}", mode).unwrap();
-                (cmd_buffer.as_str(), commands::AT_response::OK, true)
-            }
-            commands::AT_commands::CIFSR => ("AT+CIFSR", commands::AT_response::OK, true),
-            commands::AT_commands::CIPMUX(mode) => {
-                write!(cmd_buffer, "AT+CIPMUX={}", mode).unwrap();
-                (cmd_buffer.as_str(), commands::AT_response::OK, true)
-            }
-            commands::AT_commands::CIPSERVER(mode) => {
-                write!(cmd_buffer, "AT+CIPSERVER={}", mode).unwrap();
-                (cmd_buffer.as_str(), commands::AT_response::OK, true)
-            }
-            commands::AT_commands::CIPSERVER_EXT(mode, port) => {
-                write!(cmd_buffer, "AT+CIPSERVER={},{}", mode, port).unwrap();
-                (cmd_buffer.as_str(), commands::AT_response::OK, true)
-            }
-            commands::AT_commands::CIPSTART(protocol, remote_ip, remote_port) => {
-                write!(
-                    cmd_buffer,
-                    "AT+CIPSTART=\"{}\",\"{}\",{}",
-                    protocol, remote_ip, remote_port
-                )
-                .unwrap();
-                (cmd_buffer.as_str(), commands::AT_response::OK, true)
-            }
-            commands::AT_commands::CIPSTART_EXT(
-                protocol,
-                remote_ip,
-                remote_port,
-                local_port,
-                mode,
-            ) => {
-                write!(
-                    cmd_buffer,
-                    "AT+CIPSTART=\"{}\",\"{}\",{},{},{}",
-                    protocol, remote_ip, remote_port, local_port, mode
-                )
-                .unwrap();
-                (cmd_buffer.as_str(), commands::AT_response::OK, true)
-            }
-            commands::AT_commands::CIPSEND(length) => {
-                write!(cmd_buffer, "AT+CIPSEND={}", length).unwrap();
-                (cmd_buffer.as_str(), commands::AT_response::OK, true)
+    fn send_command(&mut self, cmd: &commands::AT_commands) -> Result<(), Error<E>> {
+        let name = cmd.name();
+        // The command would be sent as data of the connection
+        if self.passthrough {
+            return Err(Error::InvalidState(name));
+        }
+        let mut cmd_buffer: String<128> = String::new();
+        let (expected, endChar) = cmd
+            .encode(&mut cmd_buffer)
+            .map_err(|_| Error::InvalidArgument(name))?;
+        let send_ = cmd_buffer.as_str();
+
+        let policy = self.retry_override.unwrap_or(self.retry_policy);
+        self.attempts = 0;
+        loop {
+            self.attempts = self.attempts + 1;
+            self.response.clear();
+            // Writes the send_ to the ESP device
+            self.start_timer(cmd);
+            self.write_serial(send_.as_bytes(), endChar)
+                .map_err(|e| Error::Write(name, e))?;
+
+            let response = self.wait_response(name, expected)?;
+            if response == expected || response == commands::AT_response::ALREADY_CONNECTED {
+                return Ok(());
             }
-            commands::AT_commands::SEND(data) => {
-                write!(cmd_buffer, "{}", data).unwrap();
-                (cmd_buffer.as_str(), commands::AT_response::OK, false)
+            if !policy.is_retryable(response) || self.attempts >= policy.max_attempts {
+                return Err(match response {
+                    commands::AT_response::FAIL => Error::Fail(name),
+                    commands::AT_response::ERROR => Error::Error(name),
+                    _ => Error::Busy(name),
+                });
             }
-            _ => (
-                "commands::AT_commands::NO_COMMAND",
-                commands::AT_response::UNKNOWN_COMMAND,
-                true,
-            ),
-        };
+            // Resend
+            self.delay.delay_ms(policy.backoff(self.attempts + 1) as u32);
+            self.discard_input();
+        }
+    }
 
-        let mut found_expected_resp = false;
-        // Writes the send_ to the ESP device
-        self.write_serial(send_.as_bytes(), endChar).ok();
-        while !found_expected_resp {
-            // Gets response from ESP
-            let mut other: [u8; 64] = [0; 64];
-            match self.get_response(&mut other) {
+    // Reads responses until `expected` or a response ending the command
+    // (`ERROR`, `FAIL`, busy) is received. Returns the response that ended it.
+    fn wait_response(
+        &mut self,
+        name: &'static str,
+        expected: commands::AT_response,
+    ) -> Result<commands::AT_response, Error<E>> {
+        let mut already_connected = false;
+        loop {
+            // Gets response from ESP, network data is kept in the links
+            match self.get_response(name, &mut []) {
                 Ok((cmd, len)) => {
-                    if cmd == expected {
-                        found_expected_resp = true;
-                    } else if cmd == commands::AT_response::ERROR {
-                        // Resend
-                        self.write_serial(send_.as_bytes(), endChar).ok();
+                    if already_connected
+                        && (cmd == commands::AT_response::OK || cmd == commands::AT_response::ERROR)
+                    {
+                        // End of `ALREADY CONNECTED`, usually followed by `ERROR`
+                        return Ok(commands::AT_response::ALREADY_CONNECTED);
                     } else if cmd == commands::AT_response::ALREADY_CONNECTED {
-                        found_expected_resp = true;
+                        already_connected = true;
+                    } else if cmd == expected
+                        || cmd == commands::AT_response::ERROR
+                        || cmd == commands::AT_response::FAIL
+                        || cmd == commands::AT_response::busy_p
+                        || cmd == commands::AT_response::busy_s
+                    {
+                        return Ok(cmd);
+                    } else if cmd == commands::AT_response::ready {
+                        // The device restarted on its own
+                        self.device_reset();
+                        return Err(Error::Reset(name));
                     } else if cmd == commands::AT_response::WIFI_CONNECTED {
                         self.connection_status = true;
                     //self.delay.delay_ms(2000u16);
                     } else if cmd == commands::AT_response::WIFI_DISCONNECT {
                         self.connection_status = false;
                         self.got_ip = false;
+                        self.network.station_ip = None;
                     //self.delay.delay_ms(2000u16);
                     } else if cmd == commands::AT_response::WIFI_GOT_IP {
                         self.got_ip = true;
-                    } else {
-                        found_expected_resp = false;
-                        self.delay.delay_ms(200u16);
-                        self.write_serial(send_.as_bytes(), endChar).ok();
                     }
                 }
-                Err(_) => found_expected_resp = false,
+                // Network data that arrived in the middle of the response
+                Err(Error::Parse(_)) | Err(Error::BufferOverflow(_)) => {}
+                Err(Error::Timeout(_)) if already_connected => {
+                    return Ok(commands::AT_response::ALREADY_CONNECTED);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // Sends `data` in chunks of at most `MAX_SEND_LEN` bytes, on the
+    // connection of `socket` or on the only one in single connection mode
+    fn send_chunks(&mut self, socket: Option<&Socket>, data: &[u8]) -> Result<(), Error<E>> {
+        let mut sent = 0;
+        for chunk in data.chunks(commands::MAX_SEND_LEN) {
+            let len = chunk.len() as u16;
+            let result = match socket {
+                Some(socket) if self.mux => {
+                    self.send(commands::AT_commands::CIPSEND_MUX(socket.link_id(), len))
+                }
+                _ => self.send(commands::AT_commands::CIPSEND(len)),
+            }
+            .and_then(|_| self.send_payload(chunk));
+            match result {
+                Ok(()) => sent = sent + chunk.len(),
+                Err(e) if sent == 0 => return Err(e),
+                Err(e) => return Err(Error::PartialSend(e.command(), sent)),
             }
         }
+        Ok(())
     }
 
-    fn get_response(&mut self, mut data: &mut [u8]) -> Result<(commands::AT_response, u8), ()> {
-        // Buffer for response from ESP device
-        let mut buffer: [u8; 64] = [0; 64];
-        let mut response: commands::AT_response = commands::AT_response::UNKNOWN_COMMAND;
+    // Moves the data the device keeps for the link in passive receive mode
+    // into the buffer of the link. Returns `false` if the device keeps none.
+    fn pull_data(&mut self, link_id: usize) -> Result<bool, Error<E>> {
+        let pending = self.recv_len()?.links[link_id];
+        let len = pending.min(self.links[link_id].free());
+        if len == 0 {
+            return Ok(false);
+        }
+        self.recv_link = link_id;
+        if self.mux {
+            self.send(commands::AT_commands::CIPRECVDATA_MUX(link_id as u8, len as u16))?;
+        } else {
+            self.send(commands::AT_commands::CIPRECVDATA(len as u16))?;
+        }
+        Ok(true)
+    }
 
-        // Read from serial until
-        self.read_serial(&mut buffer);
-        /* while buffer[0] == 0 || (buffer[0] == b'\r' && buffer[1] == b'\n') {
-            self.read_serial(&mut buffer).ok();
-        } */
+    // Writes `data` after the `>` prompt of CIPSEND and waits for `SEND OK`.
+    // `Recv <len> bytes` reported in between is kept with the response.
+    fn send_payload(&mut self, data: &[u8]) -> Result<(), Error<E>> {
+        let cmd = commands::AT_commands::SEND(data);
+        // Not resent, the device already dropped the prompt
+        self.attempts = 1;
+        self.response.clear();
+        self.start_timer(&cmd);
+        self.write_serial(data, false)
+            .map_err(|e| Error::Write("SEND", e))?;
+        match self.wait_response("SEND", commands::AT_response::SEND_OK)? {
+            commands::AT_response::SEND_OK => Ok(()),
+            commands::AT_response::FAIL => Err(Error::Fail("SEND")),
+            commands::AT_response::ERROR => Err(Error::Error("SEND")),
+            _ => Err(Error::Busy("SEND")),
+        }
+    }
 
-        /* // Find where the end of the message is
-        let len = buffer.len();
-        let mut index = 0;
-        for i in 0..len {
-            if buffer[i] == 0 {
-                break;
+    // Handles the output the device started sending without waiting for more,
+    // used by the non-blocking `embedded-nal` stack. A response or frame that
+    // has started is read to its end.
+    fn poll(&mut self) -> Result<(), Error<E>> {
+        loop {
+            match self.rx.read_ready() {
+                Ok(true) => {}
+                Ok(false) => return Ok(()),
+                Err(e) => return Err(Error::Read("IPD", e)),
             }
-            index = index + 1;
-        }
-        // Break free the message for easier handling
-        let mut message = buffer.split_at_mut(index).0; */
-
-        // Find network data
-        // TODO: bound check of ':'
-        let mut data_len = 0;
-        if buffer.starts_with(b"+IPD") {
-            let mut index = 5;
-            let mut num_digit = 0;
-            while buffer[index] != b':' {
-                index = index + 1;
-                num_digit = num_digit + 1;
+            self.start_timer(&commands::AT_commands::IPD);
+            match self.get_response("IPD", &mut []) {
+                Ok((commands::AT_response::ready, _)) => {
+                    self.device_reset();
+                    return Err(Error::Reset("IPD"));
+                }
+                Ok(_) | Err(Error::BufferOverflow(_)) => {}
+                Err(e) => return Err(e),
             }
-            for i in 0..num_digit {
-                data_len = data_len + (buffer[4 + num_digit - i] - 48) * 10u8.pow(i as u32);
+        }
+    }
+
+    // Forgets the state of the device after it restarted
+    fn device_reset(&mut self) {
+        self.connection_status = false;
+        self.got_ip = false;
+        self.network = NetworkInfo::default();
+        self.mux = false;
+        for link in self.links.iter_mut() {
+            link.reset();
+        }
+        self.remote_info = false;
+        self.passive = false;
+        self.passthrough = false;
+        self.station_events.clear();
+        self.parser.reset();
+    }
+
+    // Drops stale input, e.g. more busy responses, before a command is resent
+    fn discard_input(&mut self) {
+        let mut byte = [0u8];
+        while let Ok(true) = self.rx.read_ready() {
+            if self.rx.read(&mut byte).is_err() {
+                break;
             }
-            //let m_data = buffer.split_at(index + 1).1;
-            let mut new_index = 0;
-            for i in (index + 1)..(index as usize + data_len as usize + 1)  {
-                data[new_index] = buffer[i];
-                new_index = new_index + 1;
+        }
+        self.parser.reset();
+    }
+
+    // Reads from the device until a complete response is parsed.
+    // The payload of a `+IPD` frame is copied to `data`, or to the buffer of
+    // its link in multiple connections mode.
+    fn get_response(
+        &mut self,
+        name: &'static str,
+        data: &mut [u8],
+    ) -> Result<(commands::AT_response, usize), Error<E>> {
+        loop {
+            let byte = self.read_byte(name)?;
+            match self.parser.feed(byte) {
+                Some(parser::Event::Line(line)) => {
+                    let response = parser::line_to_response(line);
+                    if response == commands::AT_response::UNKNOWN_COMMAND {
+                        // Data returned by the command
+                        self.response.push_line(line);
+                    } else if response == commands::AT_response::X_CONNECT
+                        || response == commands::AT_response::X_CLOSED
+                    {
+                        let connected = response == commands::AT_response::X_CONNECT;
+                        // The only connection of single connection mode is kept as link 0
+                        let link_id = match parser::link_id(line) {
+                            None if !self.mux => Some(0),
+                            link_id => link_id,
+                        };
+                        if let Some(link) =
+                            link_id.and_then(|link_id| self.links.get_mut(link_id as usize))
+                        {
+                            link.connected = connected;
+                        }
+                    } else if response == commands::AT_response::STA_CONNECTED
+                        || response == commands::AT_response::DIST_STA_IP
+                        || response == commands::AT_response::STA_DISCONNECTED
+                    {
+                        if let Some(event) = StationEvent::parse(line) {
+                            // Full, the oldest event is dropped
+                            if let Err(event) = self.station_events.push_back(event) {
+                                self.station_events.pop_front();
+                                self.station_events.push_back(event).ok();
+                            }
+                        }
+                    }
+                    return Ok((response, 0));
+                }
+                Some(parser::Event::TruncatedLine(_)) => {
+                    self.response.set_truncated();
+                    return Ok((commands::AT_response::UNKNOWN_COMMAND, 0));
+                }
+                Some(parser::Event::Prompt) => {
+                    return Ok((commands::AT_response::ready_to_send, 0));
+                }
+                Some(parser::Event::Ipd(parser::IpdHeader {
+                    link_id: Some(link_id),
+                    remote,
+                    ..
+                })) => {
+                    self.read_link_payload(name, link_id as usize, remote)?;
+                    return Ok((commands::AT_response::IPD, 0));
+                }
+                // The only connection of single connection mode is kept as
+                // link 0. The payload goes straight to `data` unless data
+                // received before is waiting in the link.
+                Some(parser::Event::Ipd(parser::IpdHeader {
+                    link_id: None,
+                    remote,
+                    ..
+                })) if !self.mux => {
+                    let len = if self.links[0].is_empty() && !self.links[0].used {
+                        self.links[0].remote = remote;
+                        self.read_payload(name, data)?
+                    } else {
+                        0
+                    };
+                    if self.parser.pending() > 0 {
+                        self.read_link_payload(name, 0, remote)?;
+                    }
+                    return Ok((commands::AT_response::IPD, len));
+                }
+                // Not sent in multiple connections mode, the payload is
+                // dropped as `Data`
+                Some(parser::Event::Ipd(parser::IpdHeader { link_id: None, .. })) => {}
+                // Data pulled by `pull_data`, the device does not report its sender
+                Some(parser::Event::RecvData(_)) => {
+                    let link_id = self.recv_link;
+                    self.read_link_payload(name, link_id, None)?;
+                    return Ok((commands::AT_response::IPD, 0));
+                }
+                // Rest of a frame that was abandoned
+                Some(parser::Event::Data(_)) => {}
+                None => {}
             }
-            //data = message.split_at_mut(index + 1).1;
-            //let (m_cmd, m_length) = m_crap.split_at(4);
+        }
+    }
 
-            response = commands::AT_response::IPD;
-        } else {
-            // Find the response
-            if buffer.starts_with(b"OK") {
-                response = commands::AT_response::OK;
-            } else if buffer.starts_with(b"FAIL") {
-                response = commands::AT_response::FAIL;
-            } else if buffer.starts_with(b"ready") {
-                response = commands::AT_response::ready;
-            } else if buffer.starts_with(b"> ") {
-                response = commands::AT_response::ready_to_send;
-            } else if buffer.starts_with(b"Recv") {
-                response = commands::AT_response::OK;
-            } else if buffer.starts_with(b"ALREADY CONNECTED") {
-                response = commands::AT_response::ALREADY_CONNECTED;
-            } else if buffer.starts_with(b"WIFI CONNECTED") {
-                response = commands::AT_response::WIFI_CONNECTED;
-            } else if buffer.starts_with(b"WIFI GOT IP") {
-                response = commands::AT_response::WIFI_GOT_IP;
-            } else if buffer.starts_with(b"WIFI DISCONNECT") {
-                response = commands::AT_response::WIFI_DISCONNECT;
-            } else {
-                response = commands::AT_response::UNKNOWN_COMMAND;
+    // Reads `+IPD` payload into `data` until it is full or the frame ends,
+    // the rest of the frame is left unread. Returns the number of bytes read.
+    fn read_payload(&mut self, name: &'static str, data: &mut [u8]) -> Result<usize, Error<E>> {
+        let mut len = 0;
+        while len < data.len() && self.parser.pending() > 0 {
+            let byte = self.read_byte(name)?;
+            if let Some(parser::Event::Data(byte)) = self.parser.feed(byte) {
+                data[len] = byte;
+                len = len + 1;
             }
         }
+        Ok(len)
+    }
 
-        Ok((response, data_len))
+    // Reads the rest of the `+IPD` or `+CIPRECVDATA` payload sent by `remote`
+    // into the buffer of the link, the payload is dropped for an unknown link
+    fn read_link_payload(
+        &mut self,
+        name: &'static str,
+        link_id: usize,
+        remote: Option<(Ipv4Addr, u16)>,
+    ) -> Result<(), Error<E>> {
+        if let Some(link) = self.links.get_mut(link_id) {
+            link.start_frame(remote);
+        }
+        while self.parser.pending() > 0 {
+            let byte = self.read_byte(name)?;
+            if let Some(parser::Event::Data(byte)) = self.parser.feed(byte) {
+                if let Some(link) = self.links.get_mut(link_id) {
+                    link.push(byte);
+                }
+            }
+        }
+        Ok(())
     }
 
     // Writes to the serial interface
     fn write_serial(&mut self, buffer: &[u8], endChar: bool) -> Result<(), E> {
-        let len = buffer.len();
-        for i in 0..len {
-            block!(self.tx.write((buffer[i]).into()))?;
-        }
+        self.tx.write_all(buffer)?;
         if endChar {
             // Send end characters
-            block!(self.tx.write((b'\r').into()))?;
-            block!(self.tx.write((b'\n').into()))?;
+            self.tx.write_all(b"\r\n")?;
         }
-
-        Ok(())
+        self.tx.flush()
     }
 
-    // Reads from the serial interface
-    fn read_serial(&mut self, buffer: &mut [u8]) -> Result<(), ()> {
-        let mut first_byte: u8 = 0;
-        let mut parsed_first_byte: bool = false;
-        while first_byte == 0 {
-            if let Some(byte) = block!(self.rx.read()).ok() {
-                first_byte = byte;
-            }
-            if first_byte == b'\r' {
-                if let Some(byte) = block!(self.rx.read()).ok() {
-                    parsed_first_byte = true;
-                }
-            }
-        }
+    // Starts the timer bounding the exchange of `cmd`
+    fn start_timer(&mut self, cmd: &commands::AT_commands) {
+        let timeout = self.timeout.unwrap_or(cmd.timeout());
+        self.timer.start(timeout);
+    }
 
-        // found start
-        let mut missed_byte: u8 = 0;
-        let mut parse_missed_byte: bool = false;
-        for elem in buffer {
-            if !parsed_first_byte {
-                *elem = first_byte;
-                parsed_first_byte = true;
-            } else if parse_missed_byte {
-                *elem = missed_byte;
-                parse_missed_byte = false;
-            } else {
-                if let Some(byte) = block!(self.rx.read()).ok() {
-                    if byte == b'\r' {
-                        if let Some(byte) = block!(self.rx.read()).ok() {
-                            if byte == b'\n' {
-                                break;
-                            } else {
-                                missed_byte = byte;
-                                parse_missed_byte = true;
-                            }
-                        }
-                        break;
-                    } else {
-                        *elem = byte;
+    // Reads a byte from the serial interface, gives up when the timer has expired
+    fn read_byte(&mut self, name: &'static str) -> Result<u8, Error<E>> {
+        let mut byte = [0u8];
+        loop {
+            match self.rx.read_ready() {
+                Ok(true) => match self.rx.read(&mut byte) {
+                    Ok(1) => return Ok(byte[0]),
+                    Ok(_) => {}
+                    Err(e) => return Err(Error::Read(name, e)),
+                },
+                Err(e) => return Err(Error::Read(name, e)),
+                Ok(false) => {
+                    if self.timer.expired() {
+                        // Anything partially received is not going to be completed
+                        self.parser.reset();
+                        return Err(Error::Timeout(name));
                     }
-                } else {
-                    return Err(());
                 }
             }
         }
-        Ok(())
     }
 }
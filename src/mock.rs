@@ -0,0 +1,493 @@
+//! Simulated ESP8266 for testing the driver on the host.
+//!
+//! A `Mock` is driven by a script of the commands the driver is expected to
+//! write and the bytes the simulated device answers with. Its serial, delay
+//! and timer halves are handed to `esp8266::new`, the `Mock` itself is kept to
+//! check that the whole script was played.
+//!
+//! Time is simulated: it only advances when the driver waits, i.e. in
+//! `DelayNs::delay_ms` and by 1 ms every time it polls `Timer::expired`.
+//!
+//! Faults of a real device can be injected in the script: damaged replies
+//! (`reply_with`), restarts (`reboot`), `busy p...` bursts (`busy`) and serial
+//! errors (`read_error`). `noise` damages bytes at random in every reply.
+//!
+//! With the `async` feature the halves also implement the traits of the
+//! async driver. The async delay advances the time by 1 ms every time it is
+//! polled, reads wait for the script without advancing it.
+//!
+//! # Example
+//! ```
+//! let mock = Mock::new();
+//! mock.expect("AT+CIFSR")
+//!     .reply("+CIFSR:STAIP,\"192.168.1.5\"\r\nOK\r\n");
+//!
+//! let (tx, rx, delay, timer) = mock.split();
+//! let mut esp = esp8266::new(tx, rx, delay, timer).unwrap();
+//! let info = esp.get_IP().unwrap();
+//! mock.assert_done();
+//! ```
+
+use embedded_io::{ErrorKind, ErrorType, Read, ReadReady, Write};
+use hal::delay::DelayNs;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::string::String;
+use std::vec::Vec;
+use crate::timer::Timer;
+#[cfg(feature = "async")]
+use core::future::poll_fn;
+#[cfg(feature = "async")]
+use core::task::Poll;
+
+/// Error of the simulated serial interface
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MockError;
+
+/// Damage done to a reply, see `Mock::reply_with`.
+/// Positions are byte offsets into the undamaged reply.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fault {
+    /// The byte at the position is lost
+    Drop(usize),
+    /// The byte at the position is replaced by a garbage byte
+    Garble(usize),
+    /// An unsolicited message, e.g. `"WIFI DISCONNECT\r\n"`, is sent before the position
+    Urc(usize, &'static str),
+    /// Everything from the position on is lost
+    Truncate(usize),
+}
+
+// Boot messages of the ROM, printed at 74880 baud, followed by the AT firmware banner
+const BOOT: &[u8] = b"\r\n\x8c\x9e\xf2n\x1c\x0e\xec\x12\x8c\x00l\x9c\r\nets Jan  8 2013,rst cause:2, boot mode:(3,6)\r\n\r\nready\r\n";
+
+// A step of the script
+#[derive(Debug)]
+enum Step {
+    // The driver writes these bytes
+    Expect(Vec<u8>),
+    // The device sends these bytes
+    Reply(Vec<u8>),
+    // Nothing happens for the given number of ms
+    Delay(u64),
+    // The device restarts, bytes not read yet are lost
+    Reboot,
+    // The next read fails with a serial error
+    ReadError,
+}
+
+// Random damage to the bytes sent by the device
+struct Noise {
+    // State of the xorshift generator
+    seed: u32,
+    // One in `rate` bytes is dropped, one in `rate` is garbled
+    rate: u32,
+}
+
+impl Noise {
+    fn next(&mut self) -> u32 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        self.seed
+    }
+}
+
+struct State {
+    script: VecDeque<Step>,
+    // Bytes written by the driver not matched by an `Expect` yet
+    written: Vec<u8>,
+    // Every byte written by the driver
+    log: Vec<u8>,
+    // Bytes waiting to be read by the driver
+    rx: VecDeque<u8>,
+    // Simulated time in ms
+    now: u64,
+    // End of the current `Delay` step
+    wake_at: Option<u64>,
+    // End of the count down of the timer
+    deadline: u64,
+    noise: Option<Noise>,
+    read_error: bool,
+}
+
+impl State {
+    // Plays the script up to the next step that needs the driver or time to advance
+    fn advance(&mut self) {
+        loop {
+            match self.script.front() {
+                Some(Step::Reply(bytes)) => {
+                    for byte in bytes {
+                        match self.noise {
+                            Some(ref mut noise) => {
+                                let random = noise.next();
+                                if random % noise.rate == 0 {
+                                    continue;
+                                } else if random % noise.rate == 1 {
+                                    self.rx.push_back(*byte ^ (random >> 8) as u8);
+                                    continue;
+                                }
+                            }
+                            None => {}
+                        }
+                        self.rx.push_back(*byte);
+                    }
+                }
+                Some(Step::Reboot) => {
+                    self.rx.clear();
+                    self.rx.extend(BOOT.iter().cloned());
+                }
+                Some(Step::ReadError) => self.read_error = true,
+                Some(Step::Delay(ms)) => {
+                    let wake_at = *self.wake_at.get_or_insert(self.now + ms);
+                    if self.now < wake_at {
+                        return;
+                    }
+                    self.wake_at = None;
+                }
+                Some(Step::Expect(expected)) => {
+                    if self.written != *expected {
+                        return;
+                    }
+                    self.written.clear();
+                }
+                None => return,
+            }
+            self.script.pop_front();
+        }
+    }
+
+    fn write(&mut self, byte: u8) {
+        self.log.push(byte);
+        self.written.push(byte);
+        self.advance();
+        let matches = match self.script.front() {
+            Some(Step::Expect(expected)) => expected.starts_with(&self.written),
+            _ => self.written.is_empty(),
+        };
+        if !matches {
+            panic!(
+                "unexpected write {:?}, next step is {:?}",
+                String::from_utf8_lossy(&self.written),
+                self.script.front()
+            );
+        }
+    }
+}
+
+/// Scripted simulated ESP8266
+pub struct Mock {
+    state: Rc<RefCell<State>>,
+}
+
+impl Mock {
+    pub fn new() -> Self {
+        Mock {
+            state: Rc::new(RefCell::new(State {
+                script: VecDeque::new(),
+                written: Vec::new(),
+                log: Vec::new(),
+                rx: VecDeque::new(),
+                now: 0,
+                wake_at: None,
+                deadline: 0,
+                noise: None,
+                read_error: false,
+            })),
+        }
+    }
+
+    /// Expects the driver to write the command `cmd`, followed by `\r\n`
+    pub fn expect(&self, cmd: &str) -> &Self {
+        let mut bytes = Vec::from(cmd.as_bytes());
+        bytes.extend_from_slice(b"\r\n");
+        self.push(Step::Expect(bytes))
+    }
+
+    /// Expects the driver to write exactly `bytes`
+    pub fn expect_bytes(&self, bytes: &[u8]) -> &Self {
+        self.push(Step::Expect(Vec::from(bytes)))
+    }
+
+    /// Sends `reply` to the driver once the previous steps are done.
+    /// Also used for unsolicited messages such as `WIFI DISCONNECT`.
+    pub fn reply(&self, reply: &str) -> &Self {
+        self.reply_bytes(reply.as_bytes())
+    }
+
+    /// Sends `bytes` to the driver once the previous steps are done
+    pub fn reply_bytes(&self, bytes: &[u8]) -> &Self {
+        self.push(Step::Reply(Vec::from(bytes)))
+    }
+
+    /// Sends `reply` to the driver, damaged by `faults`
+    /// # Example
+    /// ```
+    /// // "OK" with the "K" lost and a disconnect in the middle of the response
+    /// mock.expect("AT+CIFSR").reply_with(
+    ///     "+CIFSR:STAIP,\"192.168.1.5\"\r\nOK\r\n",
+    ///     &[Fault::Urc(28, "WIFI DISCONNECT\r\n"), Fault::Drop(29)],
+    /// );
+    /// ```
+    pub fn reply_with(&self, reply: &str, faults: &[Fault]) -> &Self {
+        let mut bytes = Vec::new();
+        for (index, byte) in reply.bytes().enumerate() {
+            for fault in faults {
+                if let Fault::Urc(position, urc) = *fault {
+                    if position == index {
+                        bytes.extend_from_slice(urc.as_bytes());
+                    }
+                }
+            }
+            if faults.contains(&Fault::Truncate(index)) {
+                break;
+            }
+            if faults.contains(&Fault::Drop(index)) {
+                continue;
+            }
+            if faults.contains(&Fault::Garble(index)) {
+                bytes.push(!byte);
+            } else {
+                bytes.push(byte);
+            }
+        }
+        self.push(Step::Reply(bytes))
+    }
+
+    /// The device restarts: bytes not read by the driver yet are lost and it
+    /// prints its boot messages followed by `ready`
+    pub fn reboot(&self) -> &Self {
+        self.push(Step::Reboot)
+    }
+
+    /// Sends `count` `busy p...` lines
+    pub fn busy(&self, count: usize) -> &Self {
+        let mut bytes = Vec::new();
+        for _ in 0..count {
+            bytes.extend_from_slice(b"busy p...\r\n");
+        }
+        self.push(Step::Reply(bytes))
+    }
+
+    /// The next read of the driver fails with `MockError`
+    pub fn read_error(&self) -> &Self {
+        self.push(Step::ReadError)
+    }
+
+    /// From now on, one in `rate` bytes sent by the device is dropped and one
+    /// in `rate` is garbled. `seed` makes the damage reproducible.
+    pub fn noise(&self, seed: u32, rate: u32) -> &Self {
+        assert!(seed != 0 && rate > 1);
+        self.state.borrow_mut().noise = Some(Noise {
+            seed: seed,
+            rate: rate,
+        });
+        self
+    }
+
+    /// Waits `ms` of simulated time before playing the next steps
+    pub fn delay(&self, ms: u64) -> &Self {
+        self.push(Step::Delay(ms))
+    }
+
+    /// Returns the serial, delay and timer halves to create the driver with
+    pub fn split(&self) -> (MockTx, MockRx, MockDelay, MockTimer) {
+        (
+            MockTx(self.state.clone()),
+            MockRx(self.state.clone()),
+            MockDelay(self.state.clone()),
+            MockTimer(self.state.clone()),
+        )
+    }
+
+    /// Every byte written by the driver so far
+    pub fn written(&self) -> Vec<u8> {
+        self.state.borrow().log.clone()
+    }
+
+    /// Simulated time in ms
+    pub fn now(&self) -> u64 {
+        self.state.borrow().now
+    }
+
+    /// Lets `ms` of simulated time pass, e.g. between calls of non-blocking
+    /// methods of the driver
+    pub fn sleep(&self, ms: u64) {
+        let mut state = self.state.borrow_mut();
+        state.now += ms;
+        state.advance();
+    }
+
+    /// Panics if steps of the script were not played
+    pub fn assert_done(&self) {
+        let mut state = self.state.borrow_mut();
+        state.advance();
+        assert!(
+            state.script.is_empty(),
+            "script not done, next step is {:?}",
+            state.script.front()
+        );
+    }
+
+    fn push(&self, step: Step) -> &Self {
+        self.state.borrow_mut().script.push_back(step);
+        self
+    }
+}
+
+/// Transmitting half of the simulated serial interface
+pub struct MockTx(Rc<RefCell<State>>);
+
+impl ErrorType for MockTx {
+    type Error = MockError;
+}
+
+impl Write for MockTx {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, MockError> {
+        let mut state = self.0.borrow_mut();
+        for byte in buf {
+            state.write(*byte);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), MockError> {
+        Ok(())
+    }
+}
+
+/// Receiving half of the simulated serial interface
+pub struct MockRx(Rc<RefCell<State>>);
+
+impl ErrorType for MockRx {
+    type Error = MockError;
+}
+
+impl ReadReady for MockRx {
+    fn read_ready(&mut self) -> Result<bool, MockError> {
+        let mut state = self.0.borrow_mut();
+        state.advance();
+        if state.read_error {
+            state.read_error = false;
+            return Err(MockError);
+        }
+        Ok(!state.rx.is_empty())
+    }
+}
+
+impl Read for MockRx {
+    /// Does not block, returns 0 when no bytes are waiting
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, MockError> {
+        let mut state = self.0.borrow_mut();
+        state.advance();
+        let mut len = 0;
+        while len < buf.len() {
+            match state.rx.pop_front() {
+                Some(byte) => buf[len] = byte,
+                None => break,
+            }
+            len = len + 1;
+        }
+        Ok(len)
+    }
+}
+
+impl embedded_io::Error for MockError {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Other
+    }
+}
+
+#[cfg(feature = "async")]
+impl embedded_io_async::Write for MockTx {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, MockError> {
+        let mut state = self.0.borrow_mut();
+        for byte in buf {
+            state.write(*byte);
+        }
+        Ok(buf.len())
+    }
+}
+
+#[cfg(feature = "async")]
+impl embedded_io_async::Read for MockRx {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, MockError> {
+        poll_fn(|cx| {
+            let mut state = self.0.borrow_mut();
+            state.advance();
+            if state.read_error {
+                state.read_error = false;
+                return Poll::Ready(Err(MockError));
+            }
+            if state.rx.is_empty() || buf.is_empty() {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            let mut len = 0;
+            while len < buf.len() {
+                match state.rx.pop_front() {
+                    Some(byte) => buf[len] = byte,
+                    None => break,
+                }
+                len = len + 1;
+            }
+            Poll::Ready(Ok(len))
+        })
+        .await
+    }
+}
+
+/// Delay advancing the simulated time
+pub struct MockDelay(Rc<RefCell<State>>);
+
+impl DelayNs for MockDelay {
+    fn delay_ns(&mut self, ns: u32) {
+        DelayNs::delay_ms(self, ns / 1_000_000 + (ns % 1_000_000 != 0) as u32);
+    }
+
+    fn delay_ms(&mut self, ms: u32) {
+        let mut state = self.0.borrow_mut();
+        state.now += ms as u64;
+        state.advance();
+    }
+}
+
+#[cfg(feature = "async")]
+impl embedded_hal_async::delay::DelayNs for MockDelay {
+    async fn delay_ns(&mut self, ns: u32) {
+        let ms = ns / 1_000_000 + (ns % 1_000_000 != 0) as u32;
+        embedded_hal_async::delay::DelayNs::delay_ms(self, ms).await
+    }
+
+    async fn delay_ms(&mut self, ms: u32) {
+        let wake_at = self.0.borrow().now + ms as u64;
+        poll_fn(|cx| {
+            let mut state = self.0.borrow_mut();
+            if state.now >= wake_at {
+                return Poll::Ready(());
+            }
+            state.now += 1;
+            state.advance();
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+/// Timer running on the simulated time
+pub struct MockTimer(Rc<RefCell<State>>);
+
+impl Timer for MockTimer {
+    fn start(&mut self, ms: u32) {
+        let mut state = self.0.borrow_mut();
+        state.deadline = state.now + ms as u64;
+    }
+
+    fn expired(&mut self) -> bool {
+        let mut state = self.0.borrow_mut();
+        state.now += 1;
+        state.now >= state.deadline
+    }
+}
@@ -0,0 +1,279 @@
+//! `embedded-nal` network stack on top of the driver.
+//!
+//! Sockets are connections of the device in multiple connections mode, which
+//! is switched on when the first socket is connected or listens. Only IPv4
+//! addresses are supported.
+//!
+//! `receive` and `accept` do not wait: they handle the output the device
+//! already started sending and return `WouldBlock` if no data or connection
+//! is available. The other methods send commands and block until the device
+//! responds. In passive receive mode (see `esp8266::set_passive_receive`)
+//! `receive` also sends the commands pulling the data the device keeps.
+
+use core::fmt::{Debug, Write};
+use embedded_nal::nb;
+use embedded_nal::{IpAddr, Ipv4Addr, SocketAddr, TcpClientStack, TcpFullStack, UdpClientStack};
+use embedded_io::{Read, ReadReady};
+use hal::delay::DelayNs;
+use heapless::String;
+use crate::commands::{self, MAX_SEND_LEN};
+use crate::error::Error;
+use crate::esp8266;
+use crate::socket::{Link, Socket};
+use crate::timer::Timer;
+
+/// TCP socket of `TcpClientStack` and `TcpFullStack`
+pub struct TcpSocket {
+    state: TcpState,
+}
+
+enum TcpState {
+    // Neither connected nor listening
+    Closed,
+    // Bound to a local port with `bind`
+    Bound(u16),
+    // Listening with the server of the device
+    Listening,
+    // A connection of the device
+    Connected(Socket),
+}
+
+/// UDP socket of `UdpClientStack`
+///
+/// `receive` returns one datagram at a time, the part of it that does not fit
+/// in the buffer is dropped. Datagrams longer than `commands::MAX_SEND_LEN`
+/// cannot be sent.
+pub struct UdpSocket {
+    // The connection and its remote address once connected
+    link: Option<(Socket, SocketAddr)>,
+}
+
+// Formats the IP address of `remote` for `AT+CIPSTART`
+fn remote_ip<E>(remote: &SocketAddr) -> Result<String<15>, Error<E>> {
+    match remote.ip() {
+        IpAddr::V4(ip) => {
+            let mut host = String::new();
+            write!(host, "{}", ip).unwrap();
+            Ok(host)
+        }
+        IpAddr::V6(_) => Err(Error::Unsupported("CIPSTART")),
+    }
+}
+
+// A connection accepted by the server of the device but not by `accept` yet
+fn accepted<const N: usize>(link: &Link<N>) -> bool {
+    link.connected && !link.used
+}
+
+impl<TX, RX, DELAY, TIMER, E, const N: usize> TcpClientStack for esp8266<TX, RX, DELAY, TIMER, N>
+where
+    TX: embedded_io::Write<Error = E>,
+    RX: Read<Error = E> + ReadReady,
+    DELAY: DelayNs,
+    TIMER: Timer,
+    E: Debug,
+{
+    type TcpSocket = TcpSocket;
+    type Error = Error<E>;
+
+    fn socket(&mut self) -> Result<TcpSocket, Error<E>> {
+        Ok(TcpSocket {
+            state: TcpState::Closed,
+        })
+    }
+
+    fn connect(&mut self, socket: &mut TcpSocket, remote: SocketAddr) -> nb::Result<(), Error<E>> {
+        match socket.state {
+            TcpState::Closed | TcpState::Bound(_) => {}
+            TcpState::Connected(_) => return Ok(()),
+            TcpState::Listening => return Err(nb::Error::Other(Error::InvalidState("CIPSTART"))),
+        }
+        let host = remote_ip::<E>(&remote)?;
+        let link = self.open_socket("TCP", &host, remote.port())?;
+        socket.state = TcpState::Connected(link);
+        Ok(())
+    }
+
+    fn is_connected(&mut self, socket: &TcpSocket) -> Result<bool, Error<E>> {
+        Ok(match socket.state {
+            TcpState::Connected(ref link) => esp8266::is_connected(self, link),
+            _ => false,
+        })
+    }
+
+    fn send(&mut self, socket: &mut TcpSocket, buffer: &[u8]) -> nb::Result<usize, Error<E>> {
+        match socket.state {
+            TcpState::Connected(ref link) => {
+                let len = buffer.len().min(MAX_SEND_LEN);
+                self.send_socket_bytes(link, &buffer[..len])?;
+                Ok(len)
+            }
+            _ => Err(nb::Error::Other(Error::InvalidState("SEND"))),
+        }
+    }
+
+    fn receive(&mut self, socket: &mut TcpSocket, buffer: &mut [u8]) -> nb::Result<usize, Error<E>> {
+        match socket.state {
+            TcpState::Connected(ref link) => {
+                self.poll()?;
+                let link_id = link.link_id() as usize;
+                // The device keeps the data until it is pulled
+                if self.passive && self.links[link_id].is_empty() {
+                    self.pull_data(link_id)?;
+                }
+                let rx = &self.links[link_id];
+                if rx.is_empty() && rx.connected && !rx.overflowed {
+                    return Err(nb::Error::WouldBlock);
+                }
+                Ok(self.read_socket(link, buffer)?)
+            }
+            _ => Err(nb::Error::Other(Error::InvalidState("IPD"))),
+        }
+    }
+
+    fn close(&mut self, socket: TcpSocket) -> Result<(), Error<E>> {
+        match socket.state {
+            TcpState::Connected(link) => self.close_socket(link),
+            TcpState::Listening => self.send(commands::AT_commands::CIPSERVER(0)),
+            TcpState::Closed | TcpState::Bound(_) => Ok(()),
+        }
+    }
+}
+
+impl<TX, RX, DELAY, TIMER, E, const N: usize> TcpFullStack for esp8266<TX, RX, DELAY, TIMER, N>
+where
+    TX: embedded_io::Write<Error = E>,
+    RX: Read<Error = E> + ReadReady,
+    DELAY: DelayNs,
+    TIMER: Timer,
+    E: Debug,
+{
+    fn bind(&mut self, socket: &mut TcpSocket, local_port: u16) -> Result<(), Error<E>> {
+        match socket.state {
+            TcpState::Closed | TcpState::Bound(_) => {
+                socket.state = TcpState::Bound(local_port);
+                Ok(())
+            }
+            _ => Err(Error::InvalidState("CIPSERVER")),
+        }
+    }
+
+    /// Starts the server of the device, it only has one
+    fn listen(&mut self, socket: &mut TcpSocket) -> Result<(), Error<E>> {
+        let port = match socket.state {
+            TcpState::Bound(port) => port,
+            _ => return Err(Error::InvalidState("CIPSERVER")),
+        };
+        if !self.mux {
+            self.send(commands::AT_commands::CIPMUX(1))?;
+            self.mux = true;
+        }
+        self.send(commands::AT_commands::CIPSERVER_EXT(1, port))?;
+        socket.state = TcpState::Listening;
+        Ok(())
+    }
+
+    fn accept(&mut self, socket: &mut TcpSocket) -> nb::Result<(TcpSocket, SocketAddr), Error<E>> {
+        match socket.state {
+            TcpState::Listening => {}
+            _ => return Err(nb::Error::Other(Error::InvalidState("CIPSERVER"))),
+        }
+        self.poll()?;
+        let link_id = match self.links.iter().position(accepted) {
+            Some(link_id) => link_id,
+            None => return Err(nb::Error::WouldBlock),
+        };
+
+        // The remote address is only reported by AT+CIPSTATUS
+        let status = self.get_status()?;
+        let remote = match status.links.iter().find(|link| link.link_id as usize == link_id) {
+            Some(link) => SocketAddr::new(
+                IpAddr::V4(Ipv4Addr::from(link.remote_ip.octets())),
+                link.remote_port,
+            ),
+            // Closed in the meantime, data it sent can still be read
+            None => SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 0),
+        };
+        self.links[link_id].used = true;
+        let connection = TcpSocket {
+            state: TcpState::Connected(Socket::new(link_id as u8)),
+        };
+        Ok((connection, remote))
+    }
+}
+
+impl<TX, RX, DELAY, TIMER, E, const N: usize> UdpClientStack for esp8266<TX, RX, DELAY, TIMER, N>
+where
+    TX: embedded_io::Write<Error = E>,
+    RX: Read<Error = E> + ReadReady,
+    DELAY: DelayNs,
+    TIMER: Timer,
+    E: Debug,
+{
+    type UdpSocket = UdpSocket;
+    type Error = Error<E>;
+
+    fn socket(&mut self) -> Result<UdpSocket, Error<E>> {
+        Ok(UdpSocket { link: None })
+    }
+
+    fn connect(&mut self, socket: &mut UdpSocket, remote: SocketAddr) -> Result<(), Error<E>> {
+        if let Some((link, _)) = socket.link.take() {
+            self.close_socket(link)?;
+        }
+        let host = remote_ip::<E>(&remote)?;
+        let link = self.open_socket("UDP", &host, remote.port())?;
+        socket.link = Some((link, remote));
+        Ok(())
+    }
+
+    fn send(&mut self, socket: &mut UdpSocket, buffer: &[u8]) -> nb::Result<(), Error<E>> {
+        match socket.link {
+            // Would be split into several datagrams
+            Some(_) if buffer.len() > MAX_SEND_LEN => {
+                Err(nb::Error::Other(Error::Unsupported("SEND")))
+            }
+            Some((ref link, _)) => Ok(self.send_socket_bytes(link, buffer)?),
+            None => Err(nb::Error::Other(Error::InvalidState("SEND"))),
+        }
+    }
+
+    fn receive(
+        &mut self,
+        socket: &mut UdpSocket,
+        buffer: &mut [u8],
+    ) -> nb::Result<(usize, SocketAddr), Error<E>> {
+        let (link_id, remote) = match socket.link {
+            Some((ref link, remote)) => (link.link_id() as usize, remote),
+            None => return Err(nb::Error::Other(Error::InvalidState("IPD"))),
+        };
+        self.poll()?;
+        // The device keeps the data until it is pulled
+        if self.passive && self.links[link_id].is_empty() {
+            self.pull_data(link_id)?;
+        }
+        let rx = &mut self.links[link_id];
+        // Datagrams were dropped
+        if rx.overflowed {
+            rx.overflowed = false;
+            return Err(nb::Error::Other(Error::BufferOverflow("IPD")));
+        }
+        if rx.is_empty() {
+            return Err(nb::Error::WouldBlock);
+        }
+        let (len, sender) = rx.read_datagram(buffer);
+        // The sender is only reported with `set_remote_info(true)`
+        let sender = match sender {
+            Some((ip, port)) => SocketAddr::new(IpAddr::V4(Ipv4Addr::from(ip.octets())), port),
+            None => remote,
+        };
+        Ok((len, sender))
+    }
+
+    fn close(&mut self, socket: UdpSocket) -> Result<(), Error<E>> {
+        match socket.link {
+            Some((link, _)) => self.close_socket(link),
+            None => Ok(()),
+        }
+    }
+}
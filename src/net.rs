@@ -0,0 +1,107 @@
+//! Network address types.
+
+use core::fmt;
+use crate::response::parse_number;
+
+/// An IPv4 address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Ipv4Addr([u8; 4]);
+
+impl Ipv4Addr {
+    /// The address `0.0.0.0`
+    pub const UNSPECIFIED: Ipv4Addr = Ipv4Addr([0, 0, 0, 0]);
+
+    pub fn new(a: u8, b: u8, c: u8, d: u8) -> Self {
+        Ipv4Addr([a, b, c, d])
+    }
+
+    /// The four bytes of the address
+    pub fn octets(&self) -> [u8; 4] {
+        self.0
+    }
+
+    /// Returns `true` for `0.0.0.0`, which the device reports when it has no IP
+    pub fn is_unspecified(&self) -> bool {
+        *self == Ipv4Addr::UNSPECIFIED
+    }
+
+    /// Parses an address in dotted decimal notation, e.g. `192.168.1.5`
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let mut octets = [0u8; 4];
+        let mut parts = data.split(|byte| *byte == b'.');
+        for octet in octets.iter_mut() {
+            let part = parts.next()?;
+            if part.is_empty() || part.len() > 3 || !part.iter().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            *octet = parse_number(part)?;
+        }
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(Ipv4Addr(octets))
+    }
+}
+
+impl From<[u8; 4]> for Ipv4Addr {
+    fn from(octets: [u8; 4]) -> Self {
+        Ipv4Addr(octets)
+    }
+}
+
+impl fmt::Display for Ipv4Addr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}.{}", self.0[0], self.0[1], self.0[2], self.0[3])
+    }
+}
+
+/// A MAC address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MacAddr([u8; 6]);
+
+impl MacAddr {
+    pub fn new(bytes: [u8; 6]) -> Self {
+        MacAddr(bytes)
+    }
+
+    /// The six bytes of the address
+    pub fn bytes(&self) -> [u8; 6] {
+        self.0
+    }
+
+    /// Parses an address written as six hex bytes separated by colons, e.g. `18:fe:34:a1:b2:c3`
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let mut bytes = [0u8; 6];
+        let mut parts = data.split(|byte| *byte == b':');
+        for byte in bytes.iter_mut() {
+            let part = parts.next()?;
+            if part.len() != 2 {
+                return None;
+            }
+            *byte = (hex_digit(part[0])? << 4) | hex_digit(part[1])?;
+        }
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(MacAddr(bytes))
+    }
+}
+
+impl fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            self.0[0], self.0[1], self.0[2], self.0[3], self.0[4], self.0[5]
+        )
+    }
+}
+
+fn hex_digit(digit: u8) -> Option<u8> {
+    match digit {
+        b'0'..=b'9' => Some(digit - b'0'),
+        b'a'..=b'f' => Some(digit - b'a' + 10),
+        b'A'..=b'F' => Some(digit - b'A' + 10),
+        _ => None,
+    }
+}
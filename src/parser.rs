@@ -0,0 +1,620 @@
+//! Incremental parser for the output of the ESP8266.
+//!
+//! The parser is fed one byte at a time and does not care how the bytes are
+//! split up when read from the serial interface. It never panics, malformed
+//! input ends up as (possibly truncated) lines.
+
+use crate::commands::AT_response;
+use crate::net::Ipv4Addr;
+use heapless::Vec;
+
+/// Longest `+IPD` payload the device sends, longer lengths are malformed
+pub const MAX_IPD_LEN: usize = 2920;
+
+/// Something complete found in the output of the device
+#[derive(Debug, PartialEq)]
+pub enum Event<'a> {
+    /// A line without the `\r\n` ending. Empty lines are skipped
+    Line(&'a [u8]),
+    /// A line that did not fit in the line buffer, only the start of it is kept
+    TruncatedLine(&'a [u8]),
+    /// The `> ` prompt, the device is waiting for data to send
+    Prompt,
+    /// Header of a `+IPD,<len>:` or `+IPD,<id>,<len>:` frame, followed by
+    /// `,<ip>,<port>` with `AT+CIPDINFO=1`. The next `len` bytes are `Data`
+    Ipd(IpdHeader),
+    /// Header of a `+CIPRECVDATA:<len>,` response in passive receive mode.
+    /// The next `len` bytes are `Data`
+    RecvData(usize),
+    /// A byte of `+IPD` or `+CIPRECVDATA` payload
+    Data(u8),
+}
+
+/// Header of a `+IPD` frame
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct IpdHeader {
+    /// ID of the connection, only sent in multiple connections mode
+    pub link_id: Option<u8>,
+    /// Length of the payload
+    pub len: usize,
+    /// IP address and port of the sender, only sent with `AT+CIPDINFO=1`
+    pub remote: Option<(Ipv4Addr, u16)>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum State {
+    // Reading a line
+    Line,
+    // After the `>` prompt, the space following it is dropped
+    Prompt,
+    // Reading `remaining` bytes of `+IPD` or `+CIPRECVDATA` payload
+    Data(usize),
+}
+
+/// Streaming parser turning bytes from the device into `Event`s
+pub struct Parser {
+    state: State,
+    line: Vec<u8, 256>,
+    truncated: bool,
+    // A `\r` was received last, it is part of the line unless `\n` follows
+    cr: bool,
+    // The line buffer holds a line that was already returned
+    done: bool,
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Parser {
+            state: State::Line,
+            line: Vec::new(),
+            truncated: false,
+            cr: false,
+            done: false,
+        }
+    }
+
+    /// Number of bytes of payload still to come as `Data`
+    pub fn pending(&self) -> usize {
+        match self.state {
+            State::Data(remaining) => remaining,
+            _ => 0,
+        }
+    }
+
+    /// Drops any partially parsed input
+    pub fn reset(&mut self) {
+        self.state = State::Line;
+        self.clear_line();
+    }
+
+    /// Feeds the next byte from the device. Returns an event when the byte
+    /// completes one.
+    pub fn feed(&mut self, byte: u8) -> Option<Event> {
+        if self.done {
+            self.clear_line();
+        }
+
+        match self.state {
+            State::Data(remaining) => {
+                self.state = if remaining > 1 {
+                    State::Data(remaining - 1)
+                } else {
+                    State::Line
+                };
+                return Some(Event::Data(byte));
+            }
+            State::Prompt => {
+                self.state = State::Line;
+                if byte == b' ' {
+                    return None;
+                }
+            }
+            State::Line => {}
+        }
+
+        if self.cr && byte != b'\n' {
+            self.cr = false;
+            self.push(b'\r');
+        }
+        match byte {
+            b'>' if self.line.is_empty() && !self.truncated => {
+                self.state = State::Prompt;
+                Some(Event::Prompt)
+            }
+            b'\r' => {
+                self.cr = true;
+                None
+            }
+            b'\n' => {
+                self.cr = false;
+                if self.line.is_empty() && !self.truncated {
+                    return None;
+                }
+                self.done = true;
+                if self.truncated {
+                    Some(Event::TruncatedLine(&self.line))
+                } else {
+                    Some(Event::Line(&self.line))
+                }
+            }
+            b':' if !self.truncated && self.line.starts_with(b"+IPD,") => {
+                match parse_ipd_header(&self.line[5..]) {
+                    Some(header) => {
+                        self.clear_line();
+                        if header.len > 0 {
+                            self.state = State::Data(header.len);
+                        }
+                        Some(Event::Ipd(header))
+                    }
+                    None => {
+                        self.push(byte);
+                        None
+                    }
+                }
+            }
+            b',' if !self.truncated && self.line.starts_with(b"+CIPRECVDATA:") => {
+                match parse_usize(&self.line[13..]).filter(|len| *len <= MAX_IPD_LEN) {
+                    Some(len) => {
+                        self.clear_line();
+                        if len > 0 {
+                            self.state = State::Data(len);
+                        }
+                        Some(Event::RecvData(len))
+                    }
+                    None => {
+                        self.push(byte);
+                        None
+                    }
+                }
+            }
+            _ => {
+                self.push(byte);
+                None
+            }
+        }
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.line.push(byte).is_err() {
+            self.truncated = true;
+        }
+    }
+
+    fn clear_line(&mut self) {
+        self.line.clear();
+        self.truncated = false;
+        self.cr = false;
+        self.done = false;
+    }
+}
+
+/// Converts a line from the device to the response it starts with
+pub fn line_to_response(line: &[u8]) -> AT_response {
+    if line.starts_with(b"OK") {
+        AT_response::OK
+    } else if line.starts_with(b"FAIL") {
+        AT_response::FAIL
+    } else if line.starts_with(b"ERROR") {
+        AT_response::ERROR
+    } else if line.starts_with(b"busy p") {
+        AT_response::busy_p
+    } else if line.starts_with(b"busy s") {
+        AT_response::busy_s
+    } else if line.starts_with(b"ready") {
+        AT_response::ready
+    } else if line.starts_with(b"SEND OK") {
+        AT_response::SEND_OK
+    } else if line.starts_with(b"SEND FAIL") {
+        AT_response::FAIL
+    } else if line.starts_with(b"ALREADY CONNECTED") {
+        AT_response::ALREADY_CONNECTED
+    } else if line.starts_with(b"WIFI CONNECTED") {
+        AT_response::WIFI_CONNECTED
+    } else if line.starts_with(b"WIFI GOT IP") {
+        AT_response::WIFI_GOT_IP
+    } else if line.starts_with(b"WIFI DISCONNECT") {
+        AT_response::WIFI_DISCONNECT
+    } else if line.starts_with(b"+STA_CONNECTED:") {
+        AT_response::STA_CONNECTED
+    } else if line.starts_with(b"+DIST_STA_IP:") {
+        AT_response::DIST_STA_IP
+    } else if line.starts_with(b"+STA_DISCONNECTED:") {
+        AT_response::STA_DISCONNECTED
+    } else if strip_link_id(line) == b"CONNECT" {
+        AT_response::X_CONNECT
+    } else if strip_link_id(line) == b"CLOSED" {
+        AT_response::X_CLOSED
+    } else {
+        AT_response::UNKNOWN_COMMAND
+    }
+}
+
+/// Link ID of a `<id>,CONNECT` or `<id>,CLOSED` line, `None` in single
+/// connection mode where the line is just `CONNECT` or `CLOSED`
+pub fn link_id(line: &[u8]) -> Option<u8> {
+    match line.iter().position(|byte| *byte == b',') {
+        Some(index) if index <= 3 => parse_usize(&line[..index])
+            .filter(|id| *id <= u8::max_value() as usize)
+            .map(|id| id as u8),
+        _ => None,
+    }
+}
+
+// Removes the `<id>,` in front of `CONNECT` and `CLOSED`
+fn strip_link_id(line: &[u8]) -> &[u8] {
+    match line.iter().position(|byte| *byte == b',') {
+        Some(index) if link_id(line).is_some() => &line[index + 1..],
+        _ => line,
+    }
+}
+
+// Parses the `[<id>,]<len>[,<ip>,<port>]` of a `+IPD` header
+fn parse_ipd_header(header: &[u8]) -> Option<IpdHeader> {
+    let mut fields: [&[u8]; 4] = [&[]; 4];
+    let mut count = 0;
+    for field in header.split(|byte| *byte == b',') {
+        *fields.get_mut(count)? = field;
+        count = count + 1;
+    }
+    let (link_id, len, remote) = match count {
+        1 => (None, fields[0], None),
+        2 => (Some(fields[0]), fields[1], None),
+        3 => (None, fields[0], Some((fields[1], fields[2]))),
+        _ => (Some(fields[0]), fields[1], Some((fields[2], fields[3]))),
+    };
+
+    let link_id = match link_id {
+        Some(link_id) => {
+            Some(parse_usize(link_id).filter(|id| *id <= u8::max_value() as usize)? as u8)
+        }
+        None => None,
+    };
+    let len = parse_usize(len).filter(|len| *len <= MAX_IPD_LEN)?;
+    let remote = match remote {
+        Some((ip, port)) => Some((
+            Ipv4Addr::parse(ip)?,
+            parse_usize(port).filter(|port| *port <= u16::max_value() as usize)? as u16,
+        )),
+        None => None,
+    };
+    Some(IpdHeader {
+        link_id: link_id,
+        len: len,
+        remote: remote,
+    })
+}
+
+/// Parses an unsigned decimal number, `None` if it is empty, contains
+/// anything but digits or overflows
+pub fn parse_usize(digits: &[u8]) -> Option<usize> {
+    if digits.is_empty() {
+        return None;
+    }
+    let mut value: usize = 0;
+    for digit in digits {
+        if !digit.is_ascii_digit() {
+            return None;
+        }
+        value = value
+            .checked_mul(10)?
+            .checked_add((digit - b'0') as usize)?;
+    }
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use self::std::vec::Vec;
+    use super::*;
+
+    // Owned copy of an `Event`, consecutive `Data` bytes are merged
+    #[derive(Debug, PartialEq)]
+    enum Out {
+        Line(Vec<u8>),
+        TruncatedLine(Vec<u8>),
+        Prompt,
+        Ipd(IpdHeader),
+        RecvData(usize),
+        Data(Vec<u8>),
+    }
+
+    fn line(line: &[u8]) -> Out {
+        Out::Line(line.to_vec())
+    }
+
+    fn ipd(link_id: Option<u8>, len: usize) -> Out {
+        Out::Ipd(IpdHeader {
+            link_id: link_id,
+            len: len,
+            remote: None,
+        })
+    }
+
+    fn ipd_from(link_id: Option<u8>, len: usize, ip: [u8; 4], port: u16) -> Out {
+        Out::Ipd(IpdHeader {
+            link_id: link_id,
+            len: len,
+            remote: Some((Ipv4Addr::new(ip[0], ip[1], ip[2], ip[3]), port)),
+        })
+    }
+
+    // Feeds `input` to a new parser in chunks of `chunk` bytes
+    fn parse(input: &[u8], chunk: usize) -> Vec<Out> {
+        let mut parser = Parser::new();
+        let mut events = Vec::new();
+        for bytes in input.chunks(chunk) {
+            for byte in bytes {
+                let event = match parser.feed(*byte) {
+                    Some(Event::Line(line)) => Out::Line(line.to_vec()),
+                    Some(Event::TruncatedLine(line)) => Out::TruncatedLine(line.to_vec()),
+                    Some(Event::Prompt) => Out::Prompt,
+                    Some(Event::Ipd(header)) => Out::Ipd(header),
+                    Some(Event::RecvData(len)) => Out::RecvData(len),
+                    Some(Event::Data(byte)) => {
+                        if let Some(Out::Data(data)) = events.last_mut() {
+                            data.push(byte);
+                            continue;
+                        }
+                        Out::Data([byte].to_vec())
+                    }
+                    None => continue,
+                };
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    // Parses `input` byte by byte and in every other chunk size, which must
+    // all give the same events
+    fn parse_chunked(input: &[u8]) -> Vec<Out> {
+        let events = parse(input, 1);
+        for chunk in 2..=input.len() {
+            assert_eq!(parse(input, chunk), events, "chunks of {} bytes", chunk);
+        }
+        events
+    }
+
+    #[test]
+    fn lines_and_empty_lines() {
+        assert_eq!(
+            parse_chunked(b"\r\nAT version:1.7.4.0\r\n\r\nOK\r\n"),
+            Vec::from([line(b"AT version:1.7.4.0"), line(b"OK")])
+        );
+    }
+
+    #[test]
+    fn lone_cr_is_kept_in_the_line() {
+        assert_eq!(
+            parse_chunked(b"ab\rcd\r\n\r\r\nOK\n"),
+            Vec::from([line(b"ab\rcd"), line(b"\r"), line(b"OK")])
+        );
+    }
+
+    #[test]
+    fn long_line_is_truncated_at_256_bytes() {
+        let mut input = Vec::new();
+        input.extend_from_slice(&[b'a'; 300]);
+        input.extend_from_slice(b"\r\nOK\r\n");
+        assert_eq!(
+            parse_chunked(&input),
+            Vec::from([Out::TruncatedLine([b'a'; 256].to_vec()), line(b"OK")])
+        );
+    }
+
+    #[test]
+    fn line_of_256_bytes_is_not_truncated() {
+        let mut input = Vec::new();
+        input.extend_from_slice(&[b'a'; 256]);
+        input.extend_from_slice(b"\r\n");
+        assert_eq!(parse_chunked(&input), Vec::from([Out::Line([b'a'; 256].to_vec())]));
+    }
+
+    #[test]
+    fn prompt_drops_the_following_space() {
+        assert_eq!(
+            parse_chunked(b"\r\nOK\r\n> \r\nRecv 4 bytes\r\n"),
+            Vec::from([line(b"OK"), Out::Prompt, line(b"Recv 4 bytes")])
+        );
+        assert_eq!(parse_chunked(b">OK\r\n"), Vec::from([Out::Prompt, line(b"OK")]));
+    }
+
+    #[test]
+    fn prompt_only_starts_a_line() {
+        assert_eq!(parse_chunked(b"a> b\r\n"), Vec::from([line(b"a> b")]));
+    }
+
+    #[test]
+    fn ipd_payload_is_not_parsed_as_lines() {
+        assert_eq!(
+            parse_chunked(b"+IPD,8:\r\nOK\r\n> \r\nSEND OK\r\n"),
+            Vec::from([
+                ipd(None, 8),
+                Out::Data(b"\r\nOK\r\n> ".to_vec()),
+                line(b"SEND OK"),
+            ])
+        );
+    }
+
+    #[test]
+    fn ipd_after_a_line_without_ending() {
+        assert_eq!(
+            parse_chunked(b"\r\n+IPD,2:ab+IPD,1:c"),
+            Vec::from([
+                ipd(None, 2),
+                Out::Data(b"ab".to_vec()),
+                ipd(None, 1),
+                Out::Data(b"c".to_vec()),
+            ])
+        );
+    }
+
+    #[test]
+    fn empty_ipd_has_no_data() {
+        assert_eq!(
+            parse_chunked(b"+IPD,0:OK\r\n"),
+            Vec::from([ipd(None, 0), line(b"OK")])
+        );
+    }
+
+    #[test]
+    fn largest_ipd_payload_is_data() {
+        let mut input = b"+IPD,2920:".to_vec();
+        input.extend_from_slice(&[b'x'; MAX_IPD_LEN]);
+        input.extend_from_slice(b"OK\r\n");
+        assert_eq!(
+            parse(&input, 64),
+            Vec::from([
+                ipd(None, MAX_IPD_LEN),
+                Out::Data([b'x'; MAX_IPD_LEN].to_vec()),
+                line(b"OK")
+            ])
+        );
+    }
+
+    #[test]
+    fn pending_counts_the_payload_left() {
+        let mut parser = Parser::new();
+        for byte in b"+IPD,3:a" {
+            parser.feed(*byte);
+        }
+        assert_eq!(parser.pending(), 2);
+        parser.feed(b'b');
+        parser.feed(b'c');
+        assert_eq!(parser.pending(), 0);
+    }
+
+    #[test]
+    fn ipd_with_link_id() {
+        assert_eq!(
+            parse_chunked(b"+IPD,0,5:hello\r\n+IPD,4,1:a"),
+            Vec::from([
+                ipd(Some(0), 5),
+                Out::Data(b"hello".to_vec()),
+                ipd(Some(4), 1),
+                Out::Data(b"a".to_vec()),
+            ])
+        );
+    }
+
+    #[test]
+    fn ipd_with_remote() {
+        assert_eq!(
+            parse_chunked(b"+IPD,2,192.168.1.7,4000:ab+IPD,3,5,10.0.0.2,53:cdefg"),
+            Vec::from([
+                ipd_from(None, 2, [192, 168, 1, 7], 4000),
+                Out::Data(b"ab".to_vec()),
+                ipd_from(Some(3), 5, [10, 0, 0, 2], 53),
+                Out::Data(b"cdefg".to_vec()),
+            ])
+        );
+    }
+
+    #[test]
+    fn recv_data_payload_may_hold_line_ends() {
+        assert_eq!(
+            parse_chunked(b"+CIPRECVDATA:5,a\r\nb:\r\nOK\r\n"),
+            Vec::from([
+                Out::RecvData(5),
+                Out::Data(b"a\r\nb:".to_vec()),
+                line(b"OK")
+            ])
+        );
+    }
+
+    #[test]
+    fn empty_recv_data_has_no_payload() {
+        assert_eq!(
+            parse_chunked(b"+CIPRECVDATA:0,\r\nOK\r\n"),
+            Vec::from([Out::RecvData(0), line(b"OK")])
+        );
+        assert_eq!(
+            parse_chunked(b"+CIPRECVDATA:x,ab\r\n"),
+            Vec::from([line(b"+CIPRECVDATA:x,ab")])
+        );
+    }
+
+    #[test]
+    fn connect_and_closed_with_link_id() {
+        assert_eq!(line_to_response(b"CONNECT"), AT_response::X_CONNECT);
+        assert_eq!(line_to_response(b"3,CONNECT"), AT_response::X_CONNECT);
+        assert_eq!(line_to_response(b"3,CLOSED"), AT_response::X_CLOSED);
+        assert_eq!(line_to_response(b"x,CLOSED"), AT_response::UNKNOWN_COMMAND);
+        assert_eq!(link_id(b"CONNECT"), None);
+        assert_eq!(link_id(b"3,CONNECT"), Some(3));
+    }
+
+    #[test]
+    fn malformed_ipd_headers_are_lines() {
+        assert_eq!(parse_chunked(b"+IPD,x:ab\r\n"), Vec::from([line(b"+IPD,x:ab")]));
+        assert_eq!(parse_chunked(b"+IPD,:ab\r\n"), Vec::from([line(b"+IPD,:ab")]));
+        assert_eq!(parse_chunked(b"+IPD,-1:ab\r\n"), Vec::from([line(b"+IPD,-1:ab")]));
+        assert_eq!(parse_chunked(b"+IPD,1,x:ab\r\n"), Vec::from([line(b"+IPD,1,x:ab")]));
+        assert_eq!(parse_chunked(b"+IPD,,2:ab\r\n"), Vec::from([line(b"+IPD,,2:ab")]));
+        assert_eq!(
+            parse_chunked(b"+IPD,256,2:ab\r\n"),
+            Vec::from([line(b"+IPD,256,2:ab")])
+        );
+        assert_eq!(
+            parse_chunked(b"+IPD,1,2,3,4,5:ab\r\n"),
+            Vec::from([line(b"+IPD,1,2,3,4,5:ab")])
+        );
+        assert_eq!(
+            parse_chunked(b"+IPD,2,10.0.0,53:ab\r\n"),
+            Vec::from([line(b"+IPD,2,10.0.0,53:ab")])
+        );
+        assert_eq!(
+            parse_chunked(b"+IPD,2,10.0.0.2,65536:ab\r\n"),
+            Vec::from([line(b"+IPD,2,10.0.0.2,65536:ab")])
+        );
+        // Longer than the device sends
+        assert_eq!(parse_chunked(b"+IPD,2921:ab\r\n"), Vec::from([line(b"+IPD,2921:ab")]));
+        assert_eq!(parse_chunked(b"+IPD,99999:ab\r\n"), Vec::from([line(b"+IPD,99999:ab")]));
+        // Overflows usize
+        assert_eq!(
+            parse_chunked(b"+IPD,99999999999999999999999:ab\r\n"),
+            Vec::from([line(b"+IPD,99999999999999999999999:ab")])
+        );
+    }
+
+    #[test]
+    fn truncated_ipd_header_is_a_line() {
+        let mut input = b"+IPD,".to_vec();
+        input.extend_from_slice(&[b'1'; 260]);
+        input.extend_from_slice(b":ab\r\n");
+        let mut truncated = b"+IPD,".to_vec();
+        truncated.extend_from_slice(&[b'1'; 251]);
+        assert_eq!(parse(&input, 1), Vec::from([Out::TruncatedLine(truncated)]));
+    }
+
+    #[test]
+    fn reset_drops_partial_input() {
+        let mut parser = Parser::new();
+        for byte in b"+IPD,5:ab" {
+            parser.feed(*byte);
+        }
+        parser.reset();
+        let mut events = Vec::new();
+        for byte in b"OK\r\n" {
+            if let Some(Event::Line(line)) = parser.feed(*byte) {
+                events.push(line.to_vec());
+            }
+        }
+        assert_eq!(events, [b"OK".to_vec()].to_vec());
+    }
+
+    #[test]
+    fn any_byte_is_parsed_without_panic() {
+        let mut input = Vec::new();
+        for byte in 0..=255u8 {
+            input.extend_from_slice(b"+IPD,");
+            input.push(byte);
+            input.push(b':');
+            input.push(byte);
+            input.extend_from_slice(b"\r>\n");
+        }
+        parse(&input, 1);
+    }
+}
@@ -0,0 +1,260 @@
+//! UART-WiFi passthrough mode (`AT+CIPMODE=1`).
+//!
+//! In passthrough mode the device sends everything written to it on the
+//! connection of single connection mode, and writes everything it receives
+//! as is, without `+IPD` framing. `esp8266::passthrough` returns a
+//! `Passthrough` stream implementing the `embedded-io` traits for it.
+//!
+//! The device only leaves passthrough mode on a `+++` written on its own,
+//! with no other data for a while before and after it.
+//!
+//! With `esp8266::save_trans_link` the device opens a connection and enters
+//! passthrough mode by itself whenever it starts. `BootPassthrough` tells
+//! `esp8266::init` what to do about it.
+
+use core::fmt::Debug;
+use embedded_io::{ErrorType, Read, ReadReady, Write};
+use hal::delay::DelayNs;
+use crate::commands;
+use crate::error::Error;
+use crate::esp8266;
+use crate::socket::DEFAULT_BUFFER_LEN;
+use crate::timer::Timer;
+
+// Time without data before `+++`, so the device sends the data on its own
+const ESCAPE_GUARD_MS: u32 = 20;
+// Time the device needs after `+++` before it takes commands
+const ESCAPE_DELAY_MS: u32 = 1_000;
+
+/// Connection the device opens in passthrough mode when it starts, see
+/// `esp8266::save_trans_link`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransLink<'a> {
+    /// TCP connection to `host`:`port`, with a keep alive interval in seconds
+    /// (0~7200, 0 disables it)
+    Tcp {
+        host: &'a str,
+        port: u16,
+        keep_alive: u16,
+    },
+    /// UDP connection to `host`:`port`, from `local_port` if given
+    Udp {
+        host: &'a str,
+        port: u16,
+        local_port: Option<u16>,
+    },
+}
+
+/// What `esp8266::init` does if the device started in passthrough mode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BootPassthrough {
+    /// Nothing, `init` fails with `Error::Timeout` as the device does not
+    /// answer commands. The default
+    Ignore,
+    /// Leaves passthrough mode and switches to normal mode, the device is
+    /// used with commands
+    Exit,
+    /// Leaves passthrough mode but keeps the connection, `esp8266::passthrough`
+    /// resumes sending on it
+    Adopt,
+}
+
+impl Default for BootPassthrough {
+    fn default() -> Self {
+        BootPassthrough::Ignore
+    }
+}
+
+/// Stream of a connection in passthrough mode, see `esp8266::passthrough`
+///
+/// Reads return the data received on the connection as is, waiting up to the
+/// `IPD` timeout for the first byte. Writes are sent on the connection, the
+/// device sends data after a 20 ms pause or once 2048 bytes are buffered.
+pub struct Passthrough<'a, TX, RX, DELAY, TIMER, const N: usize = { DEFAULT_BUFFER_LEN }> {
+    esp: &'a mut esp8266<TX, RX, DELAY, TIMER, N>,
+}
+
+impl<'a, TX, RX, DELAY, TIMER, E, const N: usize> Passthrough<'a, TX, RX, DELAY, TIMER, N>
+where
+    TX: Write<Error = E>,
+    RX: Read<Error = E> + ReadReady,
+    DELAY: DelayNs,
+    TIMER: Timer,
+{
+    /// Leaves passthrough mode, see `esp8266::exit_passthrough`
+    pub fn exit(self) -> Result<(), Error<E>> {
+        self.esp.exit_passthrough()
+    }
+}
+
+impl<TX, RX, DELAY, TIMER, E, const N: usize> esp8266<TX, RX, DELAY, TIMER, N>
+where
+    TX: Write<Error = E>,
+    RX: Read<Error = E> + ReadReady,
+    DELAY: DelayNs,
+    TIMER: Timer,
+{
+    /// Enters passthrough mode on the connection of single connection mode,
+    /// opened with `tcp_connect` or `udp_server`. Returns
+    /// `Error::InvalidState` in multiple connections mode or without
+    /// connection.
+    /// # Example
+    /// ```
+    /// let socket = esp.tcp_connect("192.168.1.10", 8080).unwrap();
+    /// let mut stream = esp.passthrough().unwrap();
+    /// stream.write_all(b"temperature=21.5\n").unwrap();
+    /// stream.exit().unwrap();
+    /// ```
+    pub fn passthrough(&mut self) -> Result<Passthrough<TX, RX, DELAY, TIMER, N>, Error<E>> {
+        if self.mux || !self.links[0].connected {
+            return Err(Error::InvalidState("CIPMODE"));
+        }
+        self.send(commands::AT_commands::CIPMODE(1))?;
+        self.send(commands::AT_commands::CIPSEND_PASSTHROUGH)?;
+        self.passthrough = true;
+        // Anything after the prompt is data of the connection
+        self.parser.reset();
+        Ok(Passthrough { esp: self })
+    }
+
+    /// Leaves passthrough mode with `+++` and switches back to normal mode.
+    /// Data received meanwhile is dropped.
+    ///
+    /// Commands fail with `Error::InvalidState` while in passthrough mode,
+    /// also once the `Passthrough` stream was dropped without `exit`. This
+    /// leaves it in that case.
+    pub fn exit_passthrough(&mut self) -> Result<(), Error<E>> {
+        self.escape()?;
+        self.send(commands::AT_commands::CIPMODE(0))
+    }
+
+    /// Makes the device open `link` and enter passthrough mode whenever it
+    /// starts (`AT+SAVETRANSLINK`), saved in flash. See `set_boot_passthrough`
+    /// for how `init` handles it.
+    /// # Example
+    /// ```
+    /// esp.save_trans_link(TransLink::Tcp {
+    ///     host: "192.168.1.10",
+    ///     port: 8080,
+    ///     keep_alive: 60,
+    /// })
+    /// .unwrap();
+    /// ```
+    pub fn save_trans_link(&mut self, link: TransLink) -> Result<(), Error<E>> {
+        match link {
+            TransLink::Tcp {
+                host,
+                port,
+                keep_alive,
+            } => self.send(commands::AT_commands::SAVETRANSLINK_TCP(host, port, keep_alive)),
+            TransLink::Udp {
+                host,
+                port,
+                local_port,
+            } => self.send(commands::AT_commands::SAVETRANSLINK_UDP(host, port, local_port)),
+        }
+    }
+
+    /// Stops the device from entering passthrough mode when it starts
+    pub fn clear_trans_link(&mut self) -> Result<(), Error<E>> {
+        self.send(commands::AT_commands::SAVETRANSLINK_OFF)
+    }
+
+    /// Sets what `init` does if the device started in passthrough mode
+    pub fn set_boot_passthrough(&mut self, boot: BootPassthrough) {
+        self.boot_passthrough = boot;
+    }
+
+    // Called by `init` once the escape was written and the device answers.
+    // Switches to normal mode or keeps the connection the device may have
+    // opened when it started, as `boot_passthrough` says.
+    pub(crate) fn recover_boot_passthrough(&mut self) -> Result<(), Error<E>> {
+        match self.boot_passthrough {
+            BootPassthrough::Ignore => Ok(()),
+            BootPassthrough::Exit => self.send(commands::AT_commands::CIPMODE(0)),
+            BootPassthrough::Adopt => {
+                // The saved connection, kept open by the device
+                let connected = !self.get_status()?.links.is_empty();
+                self.links[0].connected = connected;
+                Ok(())
+            }
+        }
+    }
+
+    // Writes the `+++` escape sequence with the guard times around it
+    pub(crate) fn escape(&mut self) -> Result<(), Error<E>> {
+        self.delay.delay_ms(ESCAPE_GUARD_MS);
+        self.write_serial(b"+++", false)
+            .map_err(|e| Error::Write("CIPMODE", e))?;
+        self.delay.delay_ms(ESCAPE_DELAY_MS);
+        self.discard_input();
+        self.passthrough = false;
+        Ok(())
+    }
+
+    /// Returns `true` while in passthrough mode
+    pub fn is_passthrough(&self) -> bool {
+        self.passthrough
+    }
+}
+
+impl<'a, TX, RX, DELAY, TIMER, E, const N: usize> ErrorType for Passthrough<'a, TX, RX, DELAY, TIMER, N>
+where
+    TX: Write<Error = E>,
+    RX: Read<Error = E> + ReadReady,
+    E: Debug,
+{
+    type Error = Error<E>;
+}
+
+impl<'a, TX, RX, DELAY, TIMER, E, const N: usize> Read for Passthrough<'a, TX, RX, DELAY, TIMER, N>
+where
+    TX: Write<Error = E>,
+    RX: Read<Error = E> + ReadReady,
+    DELAY: DelayNs,
+    TIMER: Timer,
+    E: Debug,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error<E>> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.esp.start_timer(&commands::AT_commands::IPD);
+        loop {
+            if self.read_ready()? {
+                let len = self.esp.rx.read(buf).map_err(|e| Error::Read("IPD", e))?;
+                if len > 0 {
+                    return Ok(len);
+                }
+            } else if self.esp.timer.expired() {
+                return Err(Error::Timeout("IPD"));
+            }
+        }
+    }
+}
+
+impl<'a, TX, RX, DELAY, TIMER, E, const N: usize> ReadReady for Passthrough<'a, TX, RX, DELAY, TIMER, N>
+where
+    TX: Write<Error = E>,
+    RX: Read<Error = E> + ReadReady,
+    E: Debug,
+{
+    fn read_ready(&mut self) -> Result<bool, Error<E>> {
+        self.esp.rx.read_ready().map_err(|e| Error::Read("IPD", e))
+    }
+}
+
+impl<'a, TX, RX, DELAY, TIMER, E, const N: usize> Write for Passthrough<'a, TX, RX, DELAY, TIMER, N>
+where
+    TX: Write<Error = E>,
+    RX: Read<Error = E> + ReadReady,
+    E: Debug,
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error<E>> {
+        self.esp.tx.write(buf).map_err(|e| Error::Write("SEND", e))
+    }
+
+    fn flush(&mut self) -> Result<(), Error<E>> {
+        self.esp.tx.flush().map_err(|e| Error::Write("SEND", e))
+    }
+}
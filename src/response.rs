@@ -0,0 +1,581 @@
+//! Data returned by query commands.
+//!
+//! Lines the device prints before the final `OK` of a command are collected
+//! in a `Response` and handed to the `Decode` implementation of the type the
+//! command returns.
+
+use core::str;
+use heapless::{String, Vec};
+use crate::commands::Encryption;
+use crate::net::{Ipv4Addr, MacAddr};
+
+/// Informational lines received while processing a command
+pub struct Response {
+    buffer: Vec<u8, 1024>,
+    truncated: bool,
+}
+
+impl Response {
+    pub fn new() -> Self {
+        Response {
+            buffer: Vec::new(),
+            truncated: false,
+        }
+    }
+
+    /// Removes all lines
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+        self.truncated = false;
+    }
+
+    /// Adds a line, lines that do not fit are dropped and mark the response as truncated
+    pub fn push_line(&mut self, line: &[u8]) {
+        if self.truncated || self.buffer.len() + line.len() + 1 > self.buffer.capacity() {
+            self.truncated = true;
+            return;
+        }
+        for byte in line.iter().chain(b"\n") {
+            self.buffer.push(*byte).ok();
+        }
+    }
+
+    /// Marks the response as missing data
+    pub fn set_truncated(&mut self) {
+        self.truncated = true;
+    }
+
+    /// Returns `true` if lines were dropped because the buffer was full
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// Iterates over the collected lines, in the order they were received
+    pub fn lines(&self) -> impl Iterator<Item = &[u8]> {
+        self.buffer
+            .split(|byte| *byte == b'\n')
+            .filter(|line| !line.is_empty())
+    }
+
+    /// Iterates over the lines starting with `prefix`, with the prefix removed
+    pub fn lines_with<'a>(&'a self, prefix: &'a [u8]) -> impl Iterator<Item = &'a [u8]> + 'a {
+        self.lines()
+            .filter(move |line| line.starts_with(prefix))
+            .map(move |line| &line[prefix.len()..])
+    }
+}
+
+/// Decodes the data returned by a command from its `Response`
+pub trait Decode: Sized {
+    /// Returns `None` if the response does not hold the expected data
+    fn decode(response: &Response) -> Option<Self>;
+}
+
+/// Iterator over the comma separated fields of a line, see `fields`
+pub struct Fields<'a> {
+    data: &'a [u8],
+    done: bool,
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        if self.done {
+            return None;
+        }
+        let mut quoted = false;
+        let mut end = self.data.len();
+        for (index, byte) in self.data.iter().enumerate() {
+            match *byte {
+                b'"' => quoted = !quoted,
+                b',' if !quoted => {
+                    end = index;
+                    break;
+                }
+                _ => {}
+            }
+        }
+        let field = &self.data[..end];
+        if end < self.data.len() {
+            self.data = &self.data[end + 1..];
+        } else {
+            self.done = true;
+        }
+        Some(unquote(field))
+    }
+}
+
+/// Splits `data` at commas outside of double quotes, the quotes around a
+/// field are removed
+pub fn fields(data: &[u8]) -> Fields {
+    Fields {
+        data: data,
+        done: false,
+    }
+}
+
+/// Removes the parentheses around `data`, as in `+CWLAP:(...)`
+pub fn unparenthesize(data: &[u8]) -> &[u8] {
+    if data.len() >= 2 && data[0] == b'(' && data[data.len() - 1] == b')' {
+        &data[1..data.len() - 1]
+    } else {
+        data
+    }
+}
+
+fn unquote(field: &[u8]) -> &[u8] {
+    if field.len() >= 2 && field[0] == b'"' && field[field.len() - 1] == b'"' {
+        &field[1..field.len() - 1]
+    } else {
+        field
+    }
+}
+
+/// Copies `data` into a `String`, `None` if it is not UTF-8 or does not fit
+pub fn to_string<const N: usize>(data: &[u8]) -> Option<String<N>> {
+    let mut string = String::new();
+    string.push_str(str::from_utf8(data).ok()?).ok()?;
+    Some(string)
+}
+
+/// Parses a signed or unsigned decimal number
+pub fn parse_number<T: str::FromStr>(data: &[u8]) -> Option<T> {
+    str::from_utf8(data).ok()?.trim().parse().ok()
+}
+
+/// Firmware version information, returned by `AT+GMR`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Version {
+    /// Version of the AT firmware, e.g. `1.7.4.0(May 11 2020 19:13:04)`
+    pub at: String<64>,
+    /// Version of the SDK, e.g. `3.0.4(9532ceb)`
+    pub sdk: String<64>,
+    /// Compilation time of the firmware
+    pub compile_time: String<64>,
+}
+
+impl Decode for Version {
+    fn decode(response: &Response) -> Option<Self> {
+        let mut version = Version {
+            at: String::new(),
+            sdk: String::new(),
+            compile_time: String::new(),
+        };
+        for line in response.lines_with(b"AT version:") {
+            version.at = to_string(line)?;
+        }
+        for line in response.lines_with(b"SDK version:") {
+            version.sdk = to_string(line)?;
+        }
+        for line in response.lines_with(b"compile time:") {
+            version.compile_time = to_string(line)?;
+        }
+        if version.at.is_empty() {
+            None
+        } else {
+            Some(version)
+        }
+    }
+}
+
+/// State of a connection, returned by `AT+CIPSTATUS`
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkStatus {
+    /// ID of the connection (0~4), 0 in single connection mode
+    pub link_id: u8,
+    /// "TCP", "UDP" or "SSL"
+    pub protocol: String<3>,
+    /// The remote IP address
+    pub remote_ip: Ipv4Addr,
+    /// The remote port number
+    pub remote_port: u16,
+    /// The local port number
+    pub local_port: u16,
+    /// `true` if the ESP8266 runs as a server on this connection
+    pub server: bool,
+}
+
+/// Connection status, returned by `AT+CIPSTATUS`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Status {
+    /// 2: Got IP
+    /// 3: Connected
+    /// 4: Disconnected
+    /// 5: Not connected to an AP
+    pub status: u8,
+    /// The open connections
+    pub links: Vec<LinkStatus, 5>,
+}
+
+impl Decode for Status {
+    fn decode(response: &Response) -> Option<Self> {
+        let status = parse_number(response.lines_with(b"STATUS:").next()?)?;
+        let mut links = Vec::new();
+        for line in response.lines_with(b"+CIPSTATUS:") {
+            let mut fields = fields(line);
+            let link = LinkStatus {
+                link_id: parse_number(fields.next()?)?,
+                protocol: to_string(fields.next()?)?,
+                remote_ip: Ipv4Addr::parse(fields.next()?)?,
+                remote_port: parse_number(fields.next()?)?,
+                local_port: parse_number(fields.next()?)?,
+                server: fields.next()? == b"1",
+            };
+            links.push(link).ok();
+        }
+        Some(Status {
+            status: status,
+            links: links,
+        })
+    }
+}
+
+/// Data kept by the device in passive receive mode, returned by `AT+CIPRECVLEN?`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RecvLen {
+    /// Number of bytes kept per connection (link ID 0~4), the connection of
+    /// single connection mode is link 0
+    pub links: [usize; 5],
+}
+
+impl Decode for RecvLen {
+    fn decode(response: &Response) -> Option<Self> {
+        let mut links = [0; 5];
+        let line = response.lines_with(b"+CIPRECVLEN:").next()?;
+        for (len, field) in links.iter_mut().zip(fields(line)) {
+            // Links that are not connected are reported as -1
+            let value: i32 = parse_number(field)?;
+            *len = if value > 0 { value as usize } else { 0 };
+        }
+        Some(RecvLen { links: links })
+    }
+}
+
+/// Local addresses of the device, returned by `AT+CIFSR`
+///
+/// The station addresses are only reported in Station and SoftAP+Station
+/// mode, the SoftAP addresses only in SoftAP and SoftAP+Station mode.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct NetworkInfo {
+    /// IP address of the Station, `0.0.0.0` when not connected to an AP
+    pub station_ip: Option<Ipv4Addr>,
+    /// MAC address of the Station
+    pub station_mac: Option<MacAddr>,
+    /// IP address of the SoftAP
+    pub ap_ip: Option<Ipv4Addr>,
+    /// MAC address of the SoftAP
+    pub ap_mac: Option<MacAddr>,
+}
+
+impl Decode for NetworkInfo {
+    fn decode(response: &Response) -> Option<Self> {
+        let mut info = NetworkInfo::default();
+        for line in response.lines_with(b"+CIFSR:") {
+            let mut fields = fields(line);
+            let (kind, address) = (fields.next()?, fields.next()?);
+            match kind {
+                b"STAIP" => info.station_ip = Some(Ipv4Addr::parse(address)?),
+                b"STAMAC" => info.station_mac = Some(MacAddr::parse(address)?),
+                b"APIP" => info.ap_ip = Some(Ipv4Addr::parse(address)?),
+                b"APMAC" => info.ap_mac = Some(MacAddr::parse(address)?),
+                _ => {}
+            }
+        }
+        if info == NetworkInfo::default() {
+            None
+        } else {
+            Some(info)
+        }
+    }
+}
+
+/// An AP found by `AT+CWLAP`
+///
+/// Fields left out of the scan by `ScanOptions::mask` are `None`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessPoint {
+    pub encryption: Option<Encryption>,
+    pub ssid: Option<String<32>>,
+    /// Signal strength in dBm
+    pub rssi: Option<i8>,
+    /// MAC address of the AP (BSSID)
+    pub mac: Option<MacAddr>,
+    pub channel: Option<u8>,
+}
+
+impl AccessPoint {
+    /// Mask bit listing the encryption
+    pub const ECN: u16 = 1 << 0;
+    /// Mask bit listing the SSID
+    pub const SSID: u16 = 1 << 1;
+    /// Mask bit listing the RSSI
+    pub const RSSI: u16 = 1 << 2;
+    /// Mask bit listing the MAC address
+    pub const MAC: u16 = 1 << 3;
+    /// Mask bit listing the channel
+    pub const CHANNEL: u16 = 1 << 4;
+    /// Mask listing all fields of `AccessPoint`
+    pub const ALL: u16 = 0x1f;
+
+    /// Parses the `(...)` of a `+CWLAP:(...)` line, listing the fields in `mask`
+    pub fn parse(data: &[u8], mask: u16) -> Option<Self> {
+        let mut ap = AccessPoint {
+            encryption: None,
+            ssid: None,
+            rssi: None,
+            mac: None,
+            channel: None,
+        };
+        let mut fields = fields(unparenthesize(data));
+        if mask & AccessPoint::ECN != 0 {
+            ap.encryption = Encryption::from_ecn(parse_number(fields.next()?)?);
+        }
+        if mask & AccessPoint::SSID != 0 {
+            ap.ssid = Some(to_string(fields.next()?)?);
+        }
+        if mask & AccessPoint::RSSI != 0 {
+            ap.rssi = Some(parse_number(fields.next()?)?);
+        }
+        if mask & AccessPoint::MAC != 0 {
+            ap.mac = Some(MacAddr::parse(fields.next()?)?);
+        }
+        if mask & AccessPoint::CHANNEL != 0 {
+            ap.channel = Some(parse_number(fields.next()?)?);
+        }
+        Some(ap)
+    }
+
+    // APs of the `+CWLAP:` lines of `response`, as many as fit
+    pub(crate) fn parse_all(response: &Response, mask: u16) -> Vec<AccessPoint, 16> {
+        let mut aps = Vec::new();
+        for line in response.lines_with(b"+CWLAP:") {
+            if let Some(ap) = AccessPoint::parse(line, mask) {
+                if aps.push(ap).is_err() {
+                    break;
+                }
+            }
+        }
+        aps
+    }
+}
+
+/// Options of a scan for APs, see `esp8266::scan`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScanOptions<'a> {
+    /// Sort the APs by RSSI, strongest first
+    pub sort_by_rssi: bool,
+    /// Fields listed for each AP, e.g. `AccessPoint::SSID | AccessPoint::RSSI`.
+    /// Listing less fields makes room for more APs in the response.
+    pub mask: u16,
+    /// Only list APs with this SSID
+    pub ssid: Option<&'a str>,
+    /// Only list the AP with this MAC address
+    pub mac: Option<MacAddr>,
+    /// Only list APs on this channel
+    pub channel: Option<u8>,
+}
+
+impl<'a> Default for ScanOptions<'a> {
+    /// All fields of all APs, unsorted
+    fn default() -> Self {
+        ScanOptions {
+            sort_by_rssi: false,
+            mask: AccessPoint::ALL,
+            ssid: None,
+            mac: None,
+            channel: None,
+        }
+    }
+}
+
+/// Configuration of the SoftAP of the device, see `esp8266::start_soft_ap`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SoftApConfig<'a> {
+    /// 1~32 bytes
+    pub ssid: &'a str,
+    /// 8~64 bytes, not used without encryption
+    pub password: &'a str,
+    /// 1~13
+    pub channel: u8,
+    /// `OPEN`, `WPA_PSK`, `WPA2_PSK` or `WPA_WPA2_PSK`
+    pub encryption: Encryption,
+    /// Maximum number of stations connected at once, 1~4
+    pub max_connections: u8,
+    /// The SSID is not broadcast
+    pub hidden: bool,
+    /// Keep the station of the device (SoftAP+Station mode) instead of
+    /// running the SoftAP only
+    pub station: bool,
+}
+
+impl<'a> SoftApConfig<'a> {
+    /// Returns `true` if the device accepts the configuration
+    pub fn is_valid(&self) -> bool {
+        let wpa = match self.encryption {
+            Encryption::OPEN => false,
+            Encryption::WPA_PSK | Encryption::WPA2_PSK | Encryption::WPA_WPA2_PSK => true,
+            // Not supported by the SoftAP
+            Encryption::WEP | Encryption::WPA2_ENTERPRISE => return false,
+        };
+        (1..=32).contains(&self.ssid.len())
+            && (!wpa || (8..=64).contains(&self.password.len()))
+            && (1..=13).contains(&self.channel)
+            && (1..=4).contains(&self.max_connections)
+    }
+}
+
+impl<'a> Default for SoftApConfig<'a> {
+    /// WPA2 on channel 1 for up to 4 stations, SSID and password are empty
+    fn default() -> Self {
+        SoftApConfig {
+            ssid: "",
+            password: "",
+            channel: 1,
+            encryption: Encryption::WPA2_PSK,
+            max_connections: 4,
+            hidden: false,
+            station: false,
+        }
+    }
+}
+
+/// Current configuration of the SoftAP, returned by `AT+CWSAP_CUR?`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SoftApInfo {
+    pub ssid: String<32>,
+    pub password: String<64>,
+    pub channel: u8,
+    pub encryption: Encryption,
+    pub max_connections: u8,
+    pub hidden: bool,
+}
+
+impl Decode for SoftApInfo {
+    fn decode(response: &Response) -> Option<Self> {
+        let mut fields = fields(response.lines_with(b"+CWSAP_CUR:").next()?);
+        let mut info = SoftApInfo {
+            ssid: to_string(fields.next()?)?,
+            password: to_string(fields.next()?)?,
+            channel: parse_number(fields.next()?)?,
+            encryption: Encryption::from_ecn(parse_number(fields.next()?)?)?,
+            max_connections: 4,
+            hidden: false,
+        };
+        // Not reported by older firmware
+        if let Some(max_connections) = fields.next() {
+            info.max_connections = parse_number(max_connections)?;
+        }
+        if let Some(hidden) = fields.next() {
+            info.hidden = hidden == b"1";
+        }
+        Some(info)
+    }
+}
+
+/// A station connected to the SoftAP, returned by `AT+CWLIF`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Station {
+    /// IP address assigned to the station by the DHCP server of the SoftAP
+    pub ip: Ipv4Addr,
+    pub mac: MacAddr,
+}
+
+impl Station {
+    /// Parses a `<ip>,<mac>` line of `AT+CWLIF`
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let mut fields = fields(data);
+        Some(Station {
+            ip: Ipv4Addr::parse(fields.next()?)?,
+            mac: MacAddr::parse(fields.next()?)?,
+        })
+    }
+}
+
+/// A station joining or leaving the SoftAP, reported by the device at any
+/// time while it runs as an AP
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StationEvent {
+    /// `+STA_CONNECTED:<mac>`, the station joined
+    Connected(MacAddr),
+    /// `+DIST_STA_IP:<mac>,<ip>`, the station was assigned an IP address
+    GotIp(MacAddr, Ipv4Addr),
+    /// `+STA_DISCONNECTED:<mac>`, the station left
+    Disconnected(MacAddr),
+}
+
+impl StationEvent {
+    /// Parses a `+STA_CONNECTED`, `+DIST_STA_IP` or `+STA_DISCONNECTED` line
+    pub fn parse(line: &[u8]) -> Option<Self> {
+        let colon = line.iter().position(|byte| *byte == b':')?;
+        let mut fields = fields(&line[colon + 1..]);
+        let mac = MacAddr::parse(fields.next()?)?;
+        match &line[..colon] {
+            b"+STA_CONNECTED" => Some(StationEvent::Connected(mac)),
+            b"+DIST_STA_IP" => Some(StationEvent::GotIp(mac, Ipv4Addr::parse(fields.next()?)?)),
+            b"+STA_DISCONNECTED" => Some(StationEvent::Disconnected(mac)),
+            _ => None,
+        }
+    }
+}
+
+/// Interfaces using DHCP, returned by `AT+CWDHCP_CUR?` and `AT+CWDHCP_DEF?`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Dhcp {
+    /// The Station gets its IP address from the AP with DHCP
+    pub station: bool,
+    /// The SoftAP leases IP addresses to its stations with DHCP
+    pub soft_ap: bool,
+}
+
+impl Decode for Dhcp {
+    fn decode(response: &Response) -> Option<Self> {
+        let line = response
+            .lines_with(b"+CWDHCP_CUR:")
+            .chain(response.lines_with(b"+CWDHCP_DEF:"))
+            .next()?;
+        // Bit 0: SoftAP, bit 1: Station, as modes 0 and 1 of `AT+CWDHCP`
+        let mask: u8 = parse_number(line)?;
+        Some(Dhcp {
+            station: mask & 2 != 0,
+            soft_ap: mask & 1 != 0,
+        })
+    }
+}
+
+/// IP addresses leased by the DHCP server of the SoftAP, returned by
+/// `AT+CWDHCPS_CUR?` and `AT+CWDHCPS_DEF?`
+///
+/// The range must be in the subnet of the SoftAP, see `esp8266::set_dhcp_range`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DhcpRange {
+    /// Lease time in minutes, 1~2880
+    pub lease_time: u16,
+    /// First IP address leased
+    pub start: Ipv4Addr,
+    /// Last IP address leased
+    pub end: Ipv4Addr,
+}
+
+impl DhcpRange {
+    /// Returns `true` if the device accepts the lease time and the range is
+    /// not empty
+    pub fn is_valid(&self) -> bool {
+        (1..=2880).contains(&self.lease_time) && self.start.octets() <= self.end.octets()
+    }
+}
+
+impl Decode for DhcpRange {
+    fn decode(response: &Response) -> Option<Self> {
+        let line = response
+            .lines_with(b"+CWDHCPS_CUR:")
+            .chain(response.lines_with(b"+CWDHCPS_DEF:"))
+            .next()?;
+        let mut fields = fields(line);
+        Some(DhcpRange {
+            lease_time: parse_number(fields.next()?)?,
+            start: Ipv4Addr::parse(fields.next()?)?,
+            end: Ipv4Addr::parse(fields.next()?)?,
+        })
+    }
+}
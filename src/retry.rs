@@ -0,0 +1,82 @@
+//! Retry policy for AT commands.
+
+use crate::commands::AT_response;
+
+/// Decides when and how often a command is resent to the device
+///
+/// The policy of the driver is set with `esp8266::set_retry_policy` and can be
+/// replaced for a single call with `esp8266::with_retry_policy`.
+/// # Example
+/// ```
+/// let policy = RetryPolicy {
+///     max_attempts: 5,
+///     fail: true,
+///     ..RetryPolicy::default()
+/// };
+/// esp.with_retry_policy(policy, |esp| esp.join_AP(ssid, pwd)).unwrap();
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// Maximum number of times a command is sent, including the first attempt
+    pub max_attempts: u8,
+    /// Delay in ms before the first resend
+    pub backoff_ms: u16,
+    /// The delay doubles for every further resend, up to this value in ms
+    pub max_backoff_ms: u16,
+    /// Resend when the device answers `busy p...`
+    pub busy_p: bool,
+    /// Resend when the device answers `busy s...`
+    pub busy_s: bool,
+    /// Resend when the device answers `ERROR`
+    pub error: bool,
+    /// Resend when the device answers `FAIL`
+    pub fail: bool,
+}
+
+impl RetryPolicy {
+    /// Sends every command exactly once
+    pub fn never() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// Returns `true` if `response` allows the command to be resent
+    pub fn is_retryable(&self, response: AT_response) -> bool {
+        match response {
+            AT_response::busy_p => self.busy_p,
+            AT_response::busy_s => self.busy_s,
+            AT_response::ERROR => self.error,
+            AT_response::FAIL => self.fail,
+            _ => false,
+        }
+    }
+
+    /// Delay in ms before sending attempt number `attempt` (the first attempt is 1)
+    pub fn backoff(&self, attempt: u8) -> u16 {
+        let doublings = attempt.saturating_sub(2).min(15) as u32;
+        let backoff = (self.backoff_ms as u32) << doublings;
+        if backoff > self.max_backoff_ms as u32 {
+            self.max_backoff_ms
+        } else {
+            backoff as u16
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    /// Three attempts, 200 ms apart and doubling up to 1 s. Resends on busy
+    /// and `ERROR` responses, but not on `FAIL`.
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            backoff_ms: 200,
+            max_backoff_ms: 1_000,
+            busy_p: true,
+            busy_s: true,
+            error: true,
+            fail: false,
+        }
+    }
+}
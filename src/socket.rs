@@ -0,0 +1,203 @@
+//! Connections of the device in multiple connections mode (`AT+CIPMUX=1`).
+//!
+//! The device numbers its connections with a link ID (0~4). Data received on
+//! a link with `+IPD,<id>,<len>:` is kept in the buffer of the link until it
+//! is read with `esp8266::read_socket`. In passive receive mode the device
+//! keeps the data until `read_socket` pulls it into that buffer. The
+//! boundaries of UDP datagrams are kept as well, for reading one datagram at
+//! a time, along with the sender of each frame when the device reports it
+//! (`esp8266::set_remote_info`).
+//!
+//! Each link buffers up to `N` bytes, the last parameter of `esp8266`, which
+//! defaults to `DEFAULT_BUFFER_LEN`. Data arriving while the buffer is full is
+//! dropped. Reading frames of the largest size the device sends before the
+//! next one arrives needs `N = parser::MAX_IPD_LEN`, see
+//! `esp8266::with_buffer_len`.
+
+use crate::net::Ipv4Addr;
+use heapless::Deque;
+
+/// Number of connections the device supports in multiple connections mode
+pub const MAX_LINKS: usize = 5;
+
+/// Bytes buffered per link unless `esp8266` is given another size
+pub const DEFAULT_BUFFER_LEN: usize = 512;
+
+// Datagrams buffered per link, more are dropped
+const MAX_DATAGRAMS: usize = 8;
+
+/// Handle of a connection opened with `esp8266::open_socket`
+#[derive(Debug, PartialEq, Eq)]
+pub struct Socket {
+    link_id: u8,
+}
+
+impl Socket {
+    pub(crate) fn new(link_id: u8) -> Self {
+        Socket { link_id: link_id }
+    }
+
+    /// ID of the connection on the device (0~4)
+    pub fn link_id(&self) -> u8 {
+        self.link_id
+    }
+}
+
+// A datagram received on a UDP link
+struct Datagram {
+    // Bytes of it in `rx`, more may still be received
+    len: usize,
+    // IP address and port of the sender, if reported
+    remote: Option<(Ipv4Addr, u16)>,
+}
+
+// State of a link of the device
+pub(crate) struct Link<const N: usize> {
+    // A `Socket` for the link was handed out
+    pub(crate) used: bool,
+    // The device reported the link as connected (`<id>,CONNECT`)
+    pub(crate) connected: bool,
+    // Received data not read yet
+    rx: Deque<u8, N>,
+    // Received data was dropped because the buffer was full
+    pub(crate) overflowed: bool,
+    // The link is a UDP connection, its data is received in datagrams
+    pub(crate) datagram: bool,
+    // The datagrams in `rx`, the last one may still be received
+    datagrams: Deque<Datagram, MAX_DATAGRAMS>,
+    // The datagram being received is dropped, too many are buffered
+    dropping: bool,
+    // Sender of the last frame, if reported
+    pub(crate) remote: Option<(Ipv4Addr, u16)>,
+}
+
+impl<const N: usize> Link<N> {
+    pub(crate) fn new() -> Self {
+        Link {
+            used: false,
+            connected: false,
+            rx: Deque::new(),
+            overflowed: false,
+            datagram: false,
+            datagrams: Deque::new(),
+            dropping: false,
+            remote: None,
+        }
+    }
+
+    // Forgets the link, dropping any data not read yet
+    pub(crate) fn reset(&mut self) {
+        self.used = false;
+        self.connected = false;
+        self.overflowed = false;
+        self.datagram = false;
+        self.dropping = false;
+        self.remote = None;
+        self.rx.clear();
+        self.datagrams.clear();
+    }
+
+    // Starts receiving a `+IPD` frame sent by `remote`, a new datagram for a
+    // UDP link
+    pub(crate) fn start_frame(&mut self, remote: Option<(Ipv4Addr, u16)>) {
+        self.remote = remote;
+        if self.datagram {
+            let datagram = Datagram {
+                len: 0,
+                remote: remote,
+            };
+            self.dropping = self.datagrams.push_back(datagram).is_err();
+            if self.dropping {
+                self.overflowed = true;
+            }
+        }
+    }
+
+    // Stores a received byte, marks the link as overflowed if it does not fit
+    pub(crate) fn push(&mut self, byte: u8) {
+        if self.dropping {
+            return;
+        }
+        if self.rx.push_back(byte).is_err() {
+            self.overflowed = true;
+        } else if let Some(datagram) = self.datagrams.back_mut() {
+            datagram.len = datagram.len + 1;
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.rx.is_empty() && self.datagrams.is_empty()
+    }
+
+    // Sender of the data read next, the sender of the last frame for a TCP
+    // link
+    pub(crate) fn sender(&self) -> Option<(Ipv4Addr, u16)> {
+        match self.datagrams.front() {
+            Some(datagram) => datagram.remote,
+            None => self.remote,
+        }
+    }
+
+    // Room left in the buffer
+    pub(crate) fn free(&self) -> usize {
+        self.rx.capacity() - self.rx.len()
+    }
+
+    // Moves as much received data as fits into `buffer`, returns its length
+    pub(crate) fn read(&mut self, buffer: &mut [u8]) -> usize {
+        let mut len = 0;
+        for elem in buffer.iter_mut() {
+            // Read as a stream, the datagrams are used up byte by byte
+            self.drop_read_datagrams();
+            match self.rx.pop_front() {
+                Some(byte) => *elem = byte,
+                None => break,
+            }
+            if let Some(datagram) = self.datagrams.front_mut() {
+                datagram.len = datagram.len - 1;
+            }
+            len = len + 1;
+        }
+        self.drop_read_datagrams();
+        len
+    }
+
+    // Like `read`, stops at the end of the datagram read first so that all
+    // the data moved has the same sender, which is returned as well
+    pub(crate) fn read_from(&mut self, buffer: &mut [u8]) -> (usize, Option<(Ipv4Addr, u16)>) {
+        let remote = self.sender();
+        let len = match self.datagrams.front() {
+            Some(datagram) if datagram.len < buffer.len() => datagram.len,
+            _ => buffer.len(),
+        };
+        (self.read(&mut buffer[..len]), remote)
+    }
+
+    // Moves the next datagram into `buffer`, the part of it that does not fit
+    // is dropped. Returns the length moved and the sender of the datagram.
+    pub(crate) fn read_datagram(&mut self, buffer: &mut [u8]) -> (usize, Option<(Ipv4Addr, u16)>) {
+        let (datagram_len, remote) = match self.datagrams.pop_front() {
+            Some(datagram) => (datagram.len, datagram.remote),
+            None => (self.rx.len(), self.remote),
+        };
+        let mut len = 0;
+        for index in 0..datagram_len {
+            let byte = match self.rx.pop_front() {
+                Some(byte) => byte,
+                None => break,
+            };
+            if let Some(elem) = buffer.get_mut(index) {
+                *elem = byte;
+                len = len + 1;
+            }
+        }
+        (len, remote)
+    }
+
+    // Removes the datagrams read completely
+    fn drop_read_datagrams(&mut self) {
+        while self.datagrams.front().map(|datagram| datagram.len) == Some(0) {
+            self.datagrams.pop_front();
+        }
+    }
+}
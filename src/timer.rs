@@ -0,0 +1,32 @@
+//! Timer abstraction used to bound AT command exchanges.
+
+/// Millisecond count down timer
+///
+/// The driver starts the timer when it writes a command to the device and
+/// gives up with `Error::Timeout` once it has expired without the expected
+/// response. `expired` is polled while waiting for bytes, so it must not block.
+///
+/// # Example
+/// ```
+/// struct SysTimer<'a> {
+///     clock: &'a Clock,
+///     deadline: u32,
+/// }
+///
+/// impl<'a> ESP8266::Timer for SysTimer<'a> {
+///     fn start(&mut self, ms: u32) {
+///         self.deadline = self.clock.millis().wrapping_add(ms);
+///     }
+///
+///     fn expired(&mut self) -> bool {
+///         (self.clock.millis().wrapping_sub(self.deadline) as i32) >= 0
+///     }
+/// }
+/// ```
+pub trait Timer {
+    /// Starts a new count down of `ms` milliseconds, cancelling any running one
+    fn start(&mut self, ms: u32);
+
+    /// Returns `true` once the count down started with `start` has run out
+    fn expired(&mut self) -> bool;
+}
@@ -0,0 +1,217 @@
+extern crate ESP8266;
+
+use std::future::Future;
+use std::pin::pin;
+use std::task::{Context, Poll, Waker};
+use ESP8266::asynch::esp8266;
+use ESP8266::mock::{Mock, MockDelay, MockRx, MockTx};
+use ESP8266::{AccessPoint, Error, Ipv4Addr, ScanOptions};
+
+type Esp = esp8266<MockTx, MockRx, MockDelay>;
+
+fn driver(mock: &Mock) -> Esp {
+    let (tx, rx, delay, _) = mock.split();
+    esp8266::new(tx, rx, delay)
+}
+
+// Polls `future` to completion, the mock never needs to be woken up
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let mut cx = Context::from_waker(Waker::noop());
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+}
+
+#[test]
+fn init_and_join_ap() {
+    let mock = Mock::new();
+    mock.expect("ATE0").reply("ATE0\r\n\r\nOK\r\n");
+    mock.expect("AT").reply("\r\nOK\r\n");
+    mock.expect("AT+CWJAP=\"ssid\",\"password\"")
+        .delay(3_000)
+        .reply("WIFI CONNECTED\r\nWIFI GOT IP\r\n\r\nOK\r\n");
+    mock.expect("AT+CIFSR")
+        .reply("+CIFSR:STAIP,\"192.168.1.5\"\r\n\r\nOK\r\n");
+    let mut esp = driver(&mock);
+
+    block_on(async {
+        esp.init().await.unwrap();
+        esp.join_AP("ssid", "password").await.unwrap();
+    });
+    assert_eq!(esp.ip(), Some(Ipv4Addr::new(192, 168, 1, 5)));
+    mock.assert_done();
+}
+
+#[test]
+fn command_times_out() {
+    let mock = Mock::new();
+    mock.expect("AT+GMR");
+    let mut esp = driver(&mock);
+
+    assert_eq!(block_on(esp.get_version()), Err(Error::Timeout("GMR")));
+    assert_eq!(mock.now(), 2_000);
+    mock.assert_done();
+}
+
+#[test]
+fn with_timeout_bounds_commands_of_the_closure() {
+    let mock = Mock::new();
+    mock.expect("AT+GMR");
+    mock.expect("AT+CIPSTATUS");
+    let mut esp = driver(&mock);
+
+    let result = block_on(esp.with_timeout(100, async |esp| esp.get_version().await));
+    assert_eq!(result, Err(Error::Timeout("GMR")));
+    assert_eq!(mock.now(), 100);
+    // The default timeout is restored
+    assert_eq!(block_on(esp.get_status()), Err(Error::Timeout("CIPSTATUS")));
+    assert_eq!(mock.now(), 2_100);
+    mock.assert_done();
+}
+
+#[test]
+fn busy_is_resent() {
+    let mock = Mock::new();
+    mock.expect("AT+GMR").busy(1);
+    mock.expect("AT+GMR").reply("AT version:1.7.4.0\r\nOK\r\n");
+    let mut esp = driver(&mock);
+
+    let version = block_on(esp.get_version()).unwrap();
+    assert_eq!(version.at, "1.7.4.0");
+    assert_eq!(esp.attempts(), 2);
+    mock.assert_done();
+}
+
+#[test]
+fn send_and_read_network_data() {
+    let mock = Mock::new();
+    mock.expect("AT+CIPSEND=4").reply("\r\nOK\r\n> ");
+    mock.expect_bytes(b"ping")
+        .reply("\r\nRecv 4 bytes\r\n\r\nSEND OK\r\n");
+    mock.delay(500).reply("+IPD,4:pong");
+    let mut esp = driver(&mock);
+
+    let mut buffer = [0u8; 16];
+    block_on(async {
+        esp.send_data("ping").await.unwrap();
+        assert_eq!(esp.read_network_data(&mut buffer).await, Ok(4));
+    });
+    assert_eq!(&buffer[..4], b"pong");
+    assert_eq!(
+        block_on(esp.read_network_data(&mut buffer)),
+        Err(Error::Timeout("IPD"))
+    );
+    mock.assert_done();
+}
+
+#[test]
+fn read_network_data_keeps_data_received_during_a_command() {
+    let mock = Mock::new();
+    mock.reply("+IPD,6:abcdef");
+    mock.expect("AT+CIPSTATUS")
+        .reply("STATUS:2\r\n+IPD,3:ghi\r\nOK\r\n");
+    let mut esp = driver(&mock);
+
+    let mut buffer = [0u8; 4];
+    block_on(async {
+        assert_eq!(esp.read_network_data(&mut buffer).await, Ok(4));
+        assert_eq!(&buffer, b"abcd");
+        esp.get_status().await.unwrap();
+        assert_eq!(esp.read_network_data(&mut buffer).await, Ok(4));
+        assert_eq!(&buffer, b"efgh");
+        assert_eq!(esp.read_network_data(&mut buffer).await, Ok(1));
+        assert_eq!(&buffer[..1], b"i");
+    });
+    mock.assert_done();
+}
+
+#[test]
+fn tcp_connect_send_read_and_close() {
+    let mock = Mock::new();
+    mock.expect("AT+CIPSTART=\"TCP\",\"example.com\",80")
+        .reply("CONNECT\r\n\r\nOK\r\n");
+    mock.expect("AT+CIPSEND=4").reply("\r\nOK\r\n> ");
+    mock.expect_bytes(b"ping")
+        .reply("\r\nRecv 4 bytes\r\n\r\nSEND OK\r\n+IPD,4:pong");
+    mock.expect("AT+CIPCLOSE").reply("CLOSED\r\n\r\nOK\r\n");
+    let mut esp = driver(&mock);
+
+    let mut buffer = [0u8; 16];
+    block_on(async {
+        esp.tcp_connect("example.com", 80).await.unwrap();
+        esp.send_data("ping").await.unwrap();
+        assert_eq!(esp.read_network_data(&mut buffer).await, Ok(4));
+        esp.close().await.unwrap();
+    });
+    assert_eq!(&buffer[..4], b"pong");
+    mock.assert_done();
+}
+
+#[test]
+fn udp_server_receives_datagrams() {
+    let mock = Mock::new();
+    mock.expect("AT+CWMODE=1").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPMUX=0").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPSTART=\"UDP\",\"0.0.0.0\",5000,5000,2")
+        .reply("CONNECT\r\n\r\nOK\r\n+IPD,3:abc");
+    let mut esp = driver(&mock);
+
+    let mut buffer = [0u8; 16];
+    block_on(async {
+        esp.udp_server(5000).await.unwrap();
+        assert_eq!(esp.read_network_data(&mut buffer).await, Ok(3));
+    });
+    assert_eq!(&buffer[..3], b"abc");
+    mock.assert_done();
+}
+
+#[test]
+fn data_with_link_id_is_refused() {
+    let mock = Mock::new();
+    mock.reply("+IPD,1,5:hello+IPD,2:ok");
+    let mut esp = driver(&mock);
+
+    let mut buffer = [0u8; 16];
+    assert_eq!(
+        block_on(esp.read_network_data(&mut buffer)),
+        Err(Error::Unsupported("IPD"))
+    );
+    assert_eq!(block_on(esp.read_network_data(&mut buffer)), Ok(2));
+    assert_eq!(&buffer[..2], b"ok");
+    mock.assert_done();
+}
+
+#[test]
+fn tcp_connect_refuses_long_host() {
+    let mock = Mock::new();
+    let mut esp = driver(&mock);
+
+    let host = "a".repeat(120);
+    assert_eq!(
+        block_on(esp.tcp_connect(&host, 80)),
+        Err(Error::InvalidArgument("CIPSTART"))
+    );
+    mock.assert_done();
+}
+
+#[test]
+fn scan_lists_access_points() {
+    let mock = Mock::new();
+    mock.expect("AT+CWLAPOPT=0,6").reply("\r\nOK\r\n");
+    mock.expect("AT+CWLAP")
+        .reply("+CWLAP:(\"home\",-52)\r\n+CWLAP:(\"guest\",-80)\r\n\r\nOK\r\n");
+    let mut esp = driver(&mock);
+
+    let options = ScanOptions {
+        mask: AccessPoint::SSID | AccessPoint::RSSI,
+        ..ScanOptions::default()
+    };
+    let aps = block_on(esp.scan(&options)).unwrap();
+    assert_eq!(aps.len(), 2);
+    assert_eq!(aps[0].ssid.as_ref().map(|s| s.as_str()), Some("home"));
+    assert_eq!(aps[1].rssi, Some(-80));
+    mock.assert_done();
+}
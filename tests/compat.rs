@@ -0,0 +1,96 @@
+extern crate embedded_hal;
+extern crate embedded_hal_02;
+extern crate embedded_io;
+extern crate nb;
+extern crate ESP8266;
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal_02::blocking::delay::DelayMs;
+use embedded_hal_02::serial;
+use embedded_io::{Read, ReadReady, Write};
+use ESP8266::compat::{Delay, SerialError};
+use ESP8266::esp8266;
+use ESP8266::mock::{Mock, MockDelay, MockError, MockRx, MockTx};
+use ESP8266::Error;
+
+// The halves of the mock as a HAL implementing embedded-hal 0.2 would provide them
+
+struct Tx02(MockTx);
+
+impl serial::Write<u8> for Tx02 {
+    type Error = MockError;
+
+    fn write(&mut self, word: u8) -> nb::Result<(), MockError> {
+        self.0.write_all(&[word]).map_err(nb::Error::Other)
+    }
+
+    fn flush(&mut self) -> nb::Result<(), MockError> {
+        Ok(())
+    }
+}
+
+struct Rx02(MockRx);
+
+impl serial::Read<u8> for Rx02 {
+    type Error = MockError;
+
+    fn read(&mut self) -> nb::Result<u8, MockError> {
+        if !self.0.read_ready()? {
+            return Err(nb::Error::WouldBlock);
+        }
+        let mut byte = [0u8];
+        self.0.read(&mut byte)?;
+        Ok(byte[0])
+    }
+}
+
+struct Delay02(MockDelay);
+
+impl DelayMs<u16> for Delay02 {
+    fn delay_ms(&mut self, ms: u16) {
+        DelayNs::delay_ms(&mut self.0, ms as u32);
+    }
+}
+
+#[test]
+fn hal02_halves_drive_the_device() {
+    let mock = Mock::new();
+    mock.expect("ATE0").reply("ATE0\r\n\r\nOK\r\n");
+    mock.expect("AT").busy(1);
+    mock.expect("AT").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPSTATUS");
+    let (tx, rx, delay, timer) = mock.split();
+    let mut esp = esp8266::new_hal02(Tx02(tx), Rx02(rx), Delay02(delay), timer).unwrap();
+
+    assert_eq!(esp.init(), Ok(()));
+    // Resent after the 200 ms back off
+    assert!(mock.now() >= 200);
+    assert_eq!(esp.get_status(), Err(Error::Timeout("CIPSTATUS")));
+    mock.assert_done();
+}
+
+#[test]
+fn hal02_serial_errors_are_wrapped() {
+    let mock = Mock::new();
+    mock.expect("AT+GMR").read_error();
+    let (tx, rx, delay, timer) = mock.split();
+    let mut esp = esp8266::new_hal02(Tx02(tx), Rx02(rx), Delay02(delay), timer).unwrap();
+
+    assert_eq!(
+        esp.get_version(),
+        Err(Error::Read("GMR", SerialError(MockError)))
+    );
+    mock.assert_done();
+}
+
+#[test]
+fn hal02_delay_rounds_ns_up_to_ms() {
+    let mock = Mock::new();
+    let (_, _, delay, _) = mock.split();
+    let mut delay = Delay(Delay02(delay));
+
+    delay.delay_ns(1);
+    assert_eq!(mock.now(), 1);
+    delay.delay_ns(u32::MAX);
+    assert_eq!(mock.now(), 1 + 4295);
+}
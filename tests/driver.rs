@@ -0,0 +1,1157 @@
+extern crate embedded_io;
+extern crate ESP8266;
+
+use embedded_io::{Read, Write};
+use ESP8266::mock::{Mock, MockDelay, MockRx, MockTimer, MockTx};
+use ESP8266::commands::{Encryption, Storage};
+use ESP8266::parser::MAX_IPD_LEN;
+use ESP8266::{
+    esp8266, AccessPoint, BootPassthrough, Dhcp, DhcpRange, Error, Ipv4Addr, MacAddr,
+    RetryPolicy, ScanOptions, SoftApConfig, Socket, Station, StationEvent, TransLink,
+};
+
+type Esp = esp8266<MockTx, MockRx, MockDelay, MockTimer>;
+
+fn driver(mock: &Mock) -> Esp {
+    let (tx, rx, delay, timer) = mock.split();
+    esp8266::new(tx, rx, delay, timer).unwrap()
+}
+
+#[test]
+fn init_turns_off_echo() {
+    let mock = Mock::new();
+    mock.expect("ATE0").reply("ATE0\r\n\r\nOK\r\n");
+    mock.expect("AT").reply("\r\nOK\r\n");
+    let mut esp = driver(&mock);
+
+    assert_eq!(esp.init(), Ok(()));
+    mock.assert_done();
+}
+
+#[test]
+fn init_times_out_without_device() {
+    let mock = Mock::new();
+    mock.expect("ATE0");
+    let mut esp = driver(&mock);
+
+    assert_eq!(esp.init(), Err(Error::Timeout("ATE")));
+    assert!(mock.now() >= 1_000);
+    mock.assert_done();
+}
+
+#[test]
+fn join_ap_reads_ip() {
+    let mock = Mock::new();
+    mock.expect("AT+CWJAP=\"ssid\",\"password\"")
+        .reply("WIFI CONNECTED\r\n")
+        .delay(3_000)
+        .reply("WIFI GOT IP\r\n\r\nOK\r\n");
+    mock.expect("AT+CIFSR")
+        .reply("+CIFSR:STAIP,\"192.168.1.5\"\r\n")
+        .reply("+CIFSR:STAMAC,\"18:fe:34:a1:b2:c3\"\r\n\r\nOK\r\n");
+    let mut esp = driver(&mock);
+
+    assert_eq!(esp.join_AP("ssid", "password"), Ok(()));
+    assert_eq!(esp.ip(), Some(Ipv4Addr::new(192, 168, 1, 5)));
+    mock.assert_done();
+}
+
+#[test]
+fn join_ap_fails_on_wrong_password() {
+    let mock = Mock::new();
+    mock.expect("AT+CWJAP=\"ssid\",\"wrong\"")
+        .reply("+CWJAP:2\r\n\r\nFAIL\r\n");
+    let mut esp = driver(&mock);
+
+    assert_eq!(esp.join_AP("ssid", "wrong"), Err(Error::Fail("CWJAP")));
+    assert_eq!(esp.attempts(), 1);
+    mock.assert_done();
+}
+
+#[test]
+fn get_ip_returns_station_and_soft_ap_addresses() {
+    let mock = Mock::new();
+    mock.expect("AT+CIFSR").reply(
+        "+CIFSR:APIP,\"192.168.4.1\"\r\n\
+         +CIFSR:APMAC,\"1a:fe:34:a1:b2:c3\"\r\n\
+         +CIFSR:STAIP,\"10.0.0.7\"\r\n\
+         +CIFSR:STAMAC,\"18:FE:34:A1:B2:C3\"\r\n\
+         \r\nOK\r\n",
+    );
+    let mut esp = driver(&mock);
+
+    let info = esp.get_IP().unwrap();
+    assert_eq!(info.station_ip, Some(Ipv4Addr::new(10, 0, 0, 7)));
+    assert_eq!(
+        info.station_mac,
+        Some(MacAddr::new([0x18, 0xfe, 0x34, 0xa1, 0xb2, 0xc3]))
+    );
+    assert_eq!(info.ap_ip, Some(Ipv4Addr::new(192, 168, 4, 1)));
+    assert_eq!(
+        info.ap_mac,
+        Some(MacAddr::new([0x1a, 0xfe, 0x34, 0xa1, 0xb2, 0xc3]))
+    );
+    assert_eq!(esp.network_info(), &info);
+    mock.assert_done();
+}
+
+#[test]
+fn get_ip_without_addresses_is_a_parse_error() {
+    let mock = Mock::new();
+    mock.expect("AT+CIFSR").reply("\r\nOK\r\n");
+    let mut esp = driver(&mock);
+
+    assert_eq!(esp.get_IP(), Err(Error::Parse("CIFSR")));
+    mock.assert_done();
+}
+
+#[test]
+fn tcp_server_enables_multiple_connections() {
+    let mock = Mock::new();
+    mock.expect("AT+CWMODE=1").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPMUX=1").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPSERVER=1,80").reply("\r\nOK\r\n");
+    let mut esp = driver(&mock);
+
+    assert_eq!(esp.tcp_server(80), Ok(()));
+    mock.assert_done();
+}
+
+#[test]
+fn tcp_server_data_is_read_with_or_without_socket() {
+    let mock = Mock::new();
+    mock.expect("AT+CWMODE=1").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPMUX=1").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPSERVER=1,80").reply("\r\nOK\r\n");
+    mock.reply("0,CONNECT\r\n+IPD,0,5:hello");
+    let mut esp = driver(&mock);
+
+    assert_eq!(esp.tcp_server(80), Ok(()));
+    let mut buffer = [0u8; 16];
+    assert_eq!(esp.read_network_data(&mut buffer), Ok(5));
+    assert_eq!(&buffer[..5], b"hello");
+
+    // Link 0 has no `Socket` yet, it is still accepted
+    assert_eq!(esp.accept_socket().map(|socket| socket.link_id()), Ok(0));
+    mock.reply("1,CONNECT\r\n+IPD,1,3:GET");
+    let client = esp.accept_socket().unwrap();
+    assert_eq!(client.link_id(), 1);
+    assert_eq!(esp.read_socket(&client, &mut buffer), Ok(3));
+    assert_eq!(&buffer[..3], b"GET");
+    assert_eq!(esp.with_timeout(10, |esp| esp.accept_socket()), Err(Error::Timeout("IPD")));
+    mock.assert_done();
+}
+
+#[test]
+fn errors_name_the_command_without_parameters() {
+    let mock = Mock::new();
+    mock.expect("AT+CWMODE=1").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPMUX=0").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPSTART=\"UDP\",\"0.0.0.0\",5000,5000,2").reply("ERROR\r\n");
+    mock.expect("AT+CWMODE=1").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPMUX=1").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPSERVER=1,80").reply("ERROR\r\n");
+    let mut esp = driver(&mock);
+    esp.set_retry_policy(RetryPolicy::never());
+
+    assert_eq!(esp.udp_server(5000).err(), Some(Error::Error("CIPSTART")));
+    assert_eq!(esp.tcp_server(80), Err(Error::Error("CIPSERVER")));
+    mock.assert_done();
+}
+
+#[test]
+fn udp_server_opens_udp_link() {
+    let mock = Mock::new();
+    mock.expect("AT+CWMODE=1").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPMUX=0").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPSTART=\"UDP\",\"0.0.0.0\",5000,5000,2")
+        .reply("CONNECT\r\n\r\nOK\r\n");
+    let mut esp = driver(&mock);
+
+    assert_eq!(esp.udp_server(5000).map(|server| server.link_id()), Ok(0));
+    mock.assert_done();
+}
+
+#[test]
+fn udp_server_answers_each_sender() {
+    let mock = Mock::new();
+    mock.expect("AT+CWMODE=1").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPMUX=0").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPSTART=\"UDP\",\"0.0.0.0\",5000,5000,2")
+        .reply("CONNECT\r\n\r\nOK\r\n");
+    mock.expect("AT+CIPDINFO=1").reply("\r\nOK\r\n");
+    mock.reply("+IPD,4,192.168.1.7,4000:ping");
+    mock.expect("AT+CIPSEND=4,\"192.168.1.7\",4000").reply("\r\nOK\r\n> ");
+    mock.expect_bytes(b"pong")
+        .reply("\r\nRecv 4 bytes\r\n\r\nSEND OK\r\n");
+    mock.reply("+IPD,4,192.168.1.8,4001:ping");
+    mock.expect("AT+CIPSEND=4,\"192.168.1.8\",4001").reply("\r\nOK\r\n> ");
+    mock.expect_bytes(b"pong")
+        .reply("\r\nRecv 4 bytes\r\n\r\nSEND OK\r\n");
+    let mut esp = driver(&mock);
+
+    let server = esp.udp_server(5000).unwrap();
+    esp.set_remote_info(true).unwrap();
+    let mut buffer = [0u8; 16];
+    for _ in 0..2 {
+        let (len, ip, port) = esp.recv_from(&mut buffer).unwrap();
+        assert_eq!(&buffer[..len], b"ping");
+        assert_eq!(esp.send_to(&server, ip, port, b"pong"), Ok(()));
+    }
+    mock.assert_done();
+}
+
+#[test]
+fn send_to_on_socket_names_link() {
+    let mock = Mock::new();
+    mock.expect("AT+CIPMUX=1").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPSTART=0,\"UDP\",\"192.168.1.11\",53")
+        .reply("0,CONNECT\r\n\r\nOK\r\n");
+    mock.expect("AT+CIPSEND=0,2,\"192.168.1.12\",53").reply("\r\nOK\r\n> ");
+    mock.expect_bytes(b"hi")
+        .reply("\r\nRecv 2 bytes\r\n\r\nSEND OK\r\n");
+    let mut esp = driver(&mock);
+
+    let udp = esp.open_socket("UDP", "192.168.1.11", 53).unwrap();
+    let remote = Ipv4Addr::new(192, 168, 1, 12);
+    assert_eq!(esp.send_to(&udp, remote, 53, b"hi"), Ok(()));
+    assert_eq!(
+        esp.send_to(&udp, remote, 53, &[0u8; 2049]),
+        Err(Error::Unsupported("SEND"))
+    );
+    mock.assert_done();
+}
+
+#[test]
+fn send_data_writes_payload_after_cipsend() {
+    let mock = Mock::new();
+    mock.expect("AT+CIPSEND=5").reply("\r\nOK\r\n> ");
+    mock.expect_bytes(b"hello")
+        .reply("\r\nRecv 5 bytes\r\n\r\nSEND OK\r\n");
+    let mut esp = driver(&mock);
+
+    assert_eq!(esp.send_data("hello"), Ok(()));
+    mock.assert_done();
+}
+
+#[test]
+fn send_bytes_writes_binary_payload_as_is() {
+    // Longer than the command buffer, with line endings and a prompt in it
+    let mut data = [0u8; 300];
+    for (index, elem) in data.iter_mut().enumerate() {
+        *elem = index as u8;
+    }
+    data[10] = b'\r';
+    data[11] = b'\n';
+    data[12] = b'>';
+    let mock = Mock::new();
+    mock.expect("AT+CIPSEND=300").reply("\r\nOK\r\n> ");
+    mock.expect_bytes(&data)
+        .reply("\r\nRecv 300 bytes\r\n\r\nSEND OK\r\n");
+    let mut esp = driver(&mock);
+
+    assert_eq!(esp.send_bytes(&data), Ok(()));
+    mock.assert_done();
+}
+
+#[test]
+fn send_bytes_reports_send_fail() {
+    let mock = Mock::new();
+    mock.expect("AT+CIPSEND=2").reply("\r\nOK\r\n> ");
+    mock.expect_bytes(&[0x00, 0xff])
+        .reply("\r\nRecv 2 bytes\r\n\r\nSEND FAIL\r\n");
+    let mut esp = driver(&mock);
+
+    assert_eq!(esp.send_bytes(&[0x00, 0xff]), Err(Error::Fail("SEND")));
+    assert_eq!(esp.attempts(), 1);
+    mock.assert_done();
+}
+
+#[test]
+fn send_bytes_splits_data_in_chunks() {
+    let data = [0x5a; 4100];
+    let mock = Mock::new();
+    mock.expect("AT+CIPSEND=2048").reply("\r\nOK\r\n> ");
+    mock.expect_bytes(&data[..2048])
+        .reply("\r\nRecv 2048 bytes\r\n\r\nSEND OK\r\n");
+    mock.expect("AT+CIPSEND=2048").reply("\r\nOK\r\n> ");
+    mock.expect_bytes(&data[2048..4096])
+        .reply("\r\nRecv 2048 bytes\r\n\r\nSEND OK\r\n");
+    mock.expect("AT+CIPSEND=4").reply("\r\nOK\r\n> ");
+    mock.expect_bytes(&data[4096..])
+        .reply("\r\nRecv 4 bytes\r\n\r\nSEND OK\r\n");
+    let mut esp = driver(&mock);
+
+    assert_eq!(esp.send_bytes(&data), Ok(()));
+    mock.assert_done();
+}
+
+#[test]
+fn send_bytes_reports_bytes_sent_before_failed_chunk() {
+    let data = [0xa5; 3000];
+    let mock = Mock::new();
+    mock.expect("AT+CIPSEND=2048").reply("\r\nOK\r\n> ");
+    mock.expect_bytes(&data[..2048])
+        .reply("\r\nRecv 2048 bytes\r\n\r\nSEND OK\r\n");
+    mock.expect("AT+CIPSEND=952").reply("\r\nOK\r\n> ");
+    mock.expect_bytes(&data[2048..])
+        .reply("\r\nRecv 952 bytes\r\n\r\nSEND FAIL\r\n");
+    let mut esp = driver(&mock);
+
+    assert_eq!(esp.send_bytes(&data), Err(Error::PartialSend("SEND", 2048)));
+    mock.assert_done();
+}
+
+#[test]
+fn read_network_data_copies_payload() {
+    let mock = Mock::new();
+    mock.reply("WIFI DISCONNECT\r\n+IPD,7:ab\r\ncd:");
+    let mut esp = driver(&mock);
+
+    let mut buffer = [0u8; 16];
+    assert_eq!(esp.read_network_data(&mut buffer), Ok(7));
+    assert_eq!(&buffer[..7], b"ab\r\ncd:");
+    mock.assert_done();
+}
+
+#[test]
+fn read_network_data_times_out() {
+    let mock = Mock::new();
+    let mut esp = driver(&mock);
+
+    let mut buffer = [0u8; 16];
+    assert_eq!(
+        esp.with_timeout(50, |esp| esp.read_network_data(&mut buffer)),
+        Err(Error::Timeout("IPD"))
+    );
+    assert_eq!(mock.now(), 50);
+}
+
+#[test]
+fn read_network_data_keeps_rest_of_frame_for_next_read() {
+    let mock = Mock::new();
+    mock.reply("+IPD,6:abcdef+IPD,2:gh");
+    let mut esp = driver(&mock);
+
+    let mut buffer = [0u8; 4];
+    assert_eq!(esp.read_network_data(&mut buffer), Ok(4));
+    assert_eq!(&buffer, b"abcd");
+    assert_eq!(esp.read_network_data(&mut buffer), Ok(2));
+    assert_eq!(&buffer[..2], b"ef");
+    assert_eq!(esp.read_network_data(&mut buffer), Ok(2));
+    assert_eq!(&buffer[..2], b"gh");
+    mock.assert_done();
+}
+
+#[test]
+fn read_network_data_reads_largest_frame() {
+    let mut frame = Vec::from(&b"+IPD,2920:"[..]);
+    frame.extend((0..2920).map(|index| index as u8));
+    let mock = Mock::new();
+    mock.reply_bytes(&frame);
+    let mut esp = driver(&mock);
+
+    let mut buffer = [0u8; 4096];
+    assert_eq!(esp.read_network_data(&mut buffer), Ok(2920));
+    assert_eq!(&buffer[..2920], &frame[10..]);
+    mock.assert_done();
+}
+
+#[test]
+fn data_received_during_a_command_is_kept() {
+    let mock = Mock::new();
+    mock.reply("+IPD,6:abcdef");
+    mock.expect("AT+CIPSTATUS")
+        .reply("STATUS:2\r\n+IPD,3:ghi\r\nOK\r\n");
+    let mut esp = driver(&mock);
+
+    let mut buffer = [0u8; 4];
+    assert_eq!(esp.read_network_data(&mut buffer), Ok(4));
+    assert_eq!(&buffer, b"abcd");
+    esp.get_status().unwrap();
+    assert_eq!(esp.read_network_data(&mut buffer), Ok(4));
+    assert_eq!(&buffer, b"efgh");
+    assert_eq!(esp.read_network_data(&mut buffer), Ok(1));
+    assert_eq!(&buffer[..1], b"i");
+    mock.assert_done();
+}
+
+#[test]
+fn data_beyond_the_buffer_of_the_driver_is_reported() {
+    let mut frame = Vec::from(&b"+IPD,600:"[..]);
+    frame.extend((0..600).map(|index| index as u8));
+    let mock = Mock::new();
+    mock.expect("AT+GMR").reply_bytes(&frame);
+    mock.reply("AT version:1.7.4.0\r\nOK\r\n");
+    let mut esp = driver(&mock);
+
+    esp.get_version().unwrap();
+    let mut buffer = [0u8; 1024];
+    assert_eq!(
+        esp.read_network_data(&mut buffer),
+        Err(Error::BufferOverflow("IPD"))
+    );
+    assert_eq!(esp.read_network_data(&mut buffer), Ok(512));
+    assert_eq!(&buffer[..512], &frame[9..521]);
+    mock.assert_done();
+}
+
+#[test]
+fn recv_from_returns_sender_of_data() {
+    let mock = Mock::new();
+    mock.expect("AT+CIPDINFO=1").reply("\r\nOK\r\n");
+    mock.reply("+IPD,5,192.168.1.7,4000:hello+IPD,3,10.0.0.2,53:abc");
+    let mut esp = driver(&mock);
+
+    let mut buffer = [0u8; 4];
+    assert_eq!(esp.recv_from(&mut buffer), Err(Error::InvalidState("CIPDINFO")));
+    assert_eq!(esp.set_remote_info(true), Ok(()));
+    let sender = Ipv4Addr::new(192, 168, 1, 7);
+    assert_eq!(esp.recv_from(&mut buffer), Ok((4, sender, 4000)));
+    assert_eq!(&buffer, b"hell");
+    // Rest of the frame
+    assert_eq!(esp.recv_from(&mut buffer), Ok((1, sender, 4000)));
+    assert_eq!(esp.recv_from(&mut buffer), Ok((3, Ipv4Addr::new(10, 0, 0, 2), 53)));
+    assert_eq!(&buffer[..3], b"abc");
+    mock.assert_done();
+}
+
+#[test]
+fn recv_from_socket_returns_sender_of_each_datagram() {
+    let mock = Mock::new();
+    mock.expect("AT+CIPDINFO=1").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPMUX=1").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPSTART=0,\"UDP\",\"192.168.1.10\",5000")
+        .reply("0,CONNECT\r\n\r\nOK\r\n");
+    mock.expect("AT+CIPSTATUS").reply(
+        "STATUS:3\r\n+IPD,0,3,192.168.1.7,4000:abc\r\n+IPD,0,2,10.0.0.2,53:de\r\nOK\r\n",
+    );
+    let mut esp = driver(&mock);
+
+    esp.set_remote_info(true).unwrap();
+    let socket = esp.open_socket("UDP", "192.168.1.10", 5000).unwrap();
+    esp.get_status().unwrap();
+    let mut buffer = [0u8; 8];
+    // Data of different senders is not merged
+    assert_eq!(
+        esp.recv_from_socket(&socket, &mut buffer),
+        Ok((3, Ipv4Addr::new(192, 168, 1, 7), 4000))
+    );
+    assert_eq!(&buffer[..3], b"abc");
+    assert_eq!(
+        esp.recv_from_socket(&socket, &mut buffer),
+        Ok((2, Ipv4Addr::new(10, 0, 0, 2), 53))
+    );
+    assert_eq!(&buffer[..2], b"de");
+    mock.assert_done();
+}
+
+#[test]
+fn sockets_demultiplex_data_by_link() {
+    let mock = Mock::new();
+    mock.expect("AT+CIPMUX=1").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPSTART=0,\"TCP\",\"192.168.1.10\",80")
+        .reply("0,CONNECT\r\n\r\nOK\r\n");
+    mock.expect("AT+CIPSTART=1,\"UDP\",\"192.168.1.11\",53")
+        .reply("1,CONNECT\r\n\r\nOK\r\n");
+    mock.expect("AT+CIPSEND=1,4").reply("\r\nOK\r\n> ");
+    mock.expect_bytes(b"ping")
+        .reply("\r\nRecv 4 bytes\r\n\r\nSEND OK\r\n");
+    mock.reply("+IPD,1,4:pong+IPD,0,5:hello+IPD,1,2:!!");
+    let mut esp = driver(&mock);
+
+    let tcp = esp.open_socket("TCP", "192.168.1.10", 80).unwrap();
+    let udp = esp.open_socket("UDP", "192.168.1.11", 53).unwrap();
+    assert_eq!((tcp.link_id(), udp.link_id()), (0, 1));
+    assert_eq!(esp.send_socket(&udp, "ping"), Ok(()));
+
+    let mut buffer = [0u8; 16];
+    assert_eq!(esp.read_socket(&tcp, &mut buffer), Ok(5));
+    assert_eq!(&buffer[..5], b"hello");
+    assert_eq!(esp.read_socket(&udp, &mut buffer), Ok(4));
+    assert_eq!(&buffer[..4], b"pong");
+    assert_eq!(esp.read_socket(&udp, &mut buffer), Ok(2));
+    assert_eq!(&buffer[..2], b"!!");
+    mock.assert_done();
+}
+
+#[test]
+fn read_socket_reads_largest_frame() {
+    let mock = Mock::new();
+    mock.expect("AT+CIPMUX=1").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPSTART=0,\"TCP\",\"192.168.1.10\",80")
+        .reply("0,CONNECT\r\n\r\nOK\r\n");
+    let payload: Vec<u8> = (0..2920).map(|i| i as u8).collect();
+    let mut frame = b"+IPD,0,2920:".to_vec();
+    frame.extend_from_slice(&payload);
+    mock.reply_bytes(&frame);
+    let (tx, rx, delay, timer) = mock.split();
+    let mut esp: esp8266<_, _, _, _, { MAX_IPD_LEN }> =
+        esp8266::with_buffer_len(tx, rx, delay, timer).unwrap();
+
+    let socket = esp.open_socket("TCP", "192.168.1.10", 80).unwrap();
+    let mut received = Vec::new();
+    let mut buffer = [0u8; 512];
+    while received.len() < payload.len() {
+        let len = esp.read_socket(&socket, &mut buffer).unwrap();
+        received.extend_from_slice(&buffer[..len]);
+    }
+    assert_eq!(received, payload);
+    mock.assert_done();
+}
+
+#[test]
+fn passive_receive_pulls_data_of_socket() {
+    let mock = Mock::new();
+    mock.expect("AT+CIPRECVMODE=1").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPMUX=1").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPSTART=0,\"TCP\",\"192.168.1.10\",80")
+        .reply("0,CONNECT\r\n\r\nOK\r\n");
+    mock.expect("AT+CIPRECVLEN?")
+        .reply("+CIPRECVLEN:0,-1,-1,-1,-1\r\n\r\nOK\r\n");
+    mock.delay(100).reply("+IPD,0,7\r\n");
+    mock.expect("AT+CIPRECVLEN?")
+        .reply("+CIPRECVLEN:7,-1,-1,-1,-1\r\n\r\nOK\r\n");
+    mock.expect("AT+CIPRECVDATA=0,7")
+        .reply("+CIPRECVDATA:7,ab\r\ncd:\r\nOK\r\n");
+    let mut esp = driver(&mock);
+
+    assert_eq!(esp.set_passive_receive(true), Ok(()));
+    let socket = esp.open_socket("TCP", "192.168.1.10", 80).unwrap();
+    let mut buffer = [0u8; 16];
+    assert_eq!(esp.read_socket(&socket, &mut buffer), Ok(7));
+    assert_eq!(&buffer[..7], b"ab\r\ncd:");
+    mock.assert_done();
+}
+
+#[test]
+fn recv_len_decodes_links() {
+    let mock = Mock::new();
+    mock.expect("AT+CIPRECVLEN?")
+        .reply("+CIPRECVLEN:0,120,-1,-1,2048\r\n\r\nOK\r\n");
+    let mut esp = driver(&mock);
+
+    assert_eq!(esp.recv_len().unwrap().links, [0, 120, 0, 0, 2048]);
+    mock.assert_done();
+}
+
+#[test]
+fn closed_socket_reads_remaining_data_then_nothing() {
+    let mock = Mock::new();
+    mock.expect("AT+CIPMUX=1").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPSTART=0,\"TCP\",\"192.168.1.10\",80")
+        .reply("0,CONNECT\r\n\r\nOK\r\n");
+    mock.reply("+IPD,0,3:bye0,CLOSED\r\n");
+    mock.expect("AT+CIPSTART=0,\"TCP\",\"192.168.1.10\",81")
+        .reply("0,CONNECT\r\n\r\nOK\r\n");
+    mock.expect("AT+CIPCLOSE=0").reply("0,CLOSED\r\n\r\nOK\r\n");
+    let mut esp = driver(&mock);
+
+    let socket = esp.open_socket("TCP", "192.168.1.10", 80).unwrap();
+    let mut buffer = [0u8; 2];
+    assert_eq!(esp.read_socket(&socket, &mut buffer), Ok(2));
+    assert_eq!(esp.read_socket(&socket, &mut buffer), Ok(1));
+    assert_eq!(&buffer[..1], b"e");
+    assert_eq!(esp.read_socket(&socket, &mut buffer), Ok(0));
+    assert!(!esp.is_connected(&socket));
+    // Closed by the remote side, nothing to send
+    assert_eq!(esp.close_socket(socket), Ok(()));
+
+    let socket = esp.open_socket("TCP", "192.168.1.10", 81).unwrap();
+    assert_eq!(socket.link_id(), 0);
+    assert_eq!(esp.close_socket(socket), Ok(()));
+    mock.assert_done();
+}
+
+#[test]
+fn tcp_connect_in_single_connection_mode() {
+    let mock = Mock::new();
+    mock.expect("AT+CIPSTART=\"TCP\",\"example.com\",80")
+        .reply("CONNECT\r\n\r\nOK\r\n");
+    mock.expect("AT+CIPSEND=4").reply("\r\nOK\r\n> ");
+    mock.expect_bytes(b"ping")
+        .reply("\r\nRecv 4 bytes\r\n");
+    mock.delay(100).reply("\r\nSEND OK\r\n");
+    mock.reply("+IPD,4:pongCLOSED\r\n");
+    let mut esp = driver(&mock);
+
+    let socket = esp.tcp_connect("example.com", 80).unwrap();
+    assert_eq!(esp.send_socket(&socket, "ping"), Ok(()));
+    // Returned only once the data was sent
+    assert!(mock.now() >= 100);
+
+    let mut buffer = [0u8; 16];
+    // The data belongs to the socket
+    assert_eq!(esp.read_network_data(&mut buffer), Err(Error::InvalidState("IPD")));
+    assert_eq!(esp.read_socket(&socket, &mut buffer), Ok(4));
+    assert_eq!(&buffer[..4], b"pong");
+    assert_eq!(esp.read_socket(&socket, &mut buffer), Ok(0));
+    assert_eq!(esp.close_socket(socket), Ok(()));
+    mock.assert_done();
+}
+
+#[test]
+fn passthrough_streams_data_until_escaped() {
+    let mock = Mock::new();
+    mock.expect("AT+CIPSTART=\"TCP\",\"192.168.1.10\",8080")
+        .reply("CONNECT\r\n\r\nOK\r\n");
+    mock.expect("AT+CIPMODE=1").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPSEND").reply("\r\nOK\r\n\r\n>");
+    mock.expect_bytes(b"GET\r\n");
+    mock.reply_bytes(b"+IPD\r\n\x00OK");
+    mock.expect_bytes(b"+++");
+    mock.reply("late data");
+    mock.expect("AT+CIPMODE=0").reply("\r\nOK\r\n");
+    let mut esp = driver(&mock);
+
+    esp.tcp_connect("192.168.1.10", 8080).unwrap();
+    let mut stream = esp.passthrough().unwrap();
+    stream.write_all(b"GET\r\n").unwrap();
+    stream.flush().unwrap();
+    // No framing, the data is returned as is
+    let mut buffer = [0u8; 16];
+    let len = stream.read(&mut buffer).unwrap();
+    assert_eq!(&buffer[..len], b"+IPD\r\n\x00OK");
+    let start = mock.now();
+    assert_eq!(stream.exit(), Ok(()));
+    // Guard times around `+++`
+    assert!(mock.now() - start >= 1_020);
+    assert!(!esp.is_passthrough());
+    mock.assert_done();
+}
+
+#[test]
+fn commands_are_refused_after_passthrough_stream_dropped() {
+    let mock = Mock::new();
+    mock.expect("AT+CIPSTART=\"TCP\",\"192.168.1.10\",8080")
+        .reply("CONNECT\r\n\r\nOK\r\n");
+    mock.expect("AT+CIPMODE=1").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPSEND").reply("\r\nOK\r\n\r\n>");
+    mock.expect_bytes(b"+++");
+    mock.expect("AT+CIPMODE=0").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPSTATUS").reply("STATUS:3\r\n\r\nOK\r\n");
+    let mut esp = driver(&mock);
+
+    esp.tcp_connect("192.168.1.10", 8080).unwrap();
+    {
+        // Dropped without `exit`
+        let _stream = esp.passthrough().unwrap();
+    }
+    // Not written, it would go out on the connection
+    assert_eq!(esp.get_status().err(), Some(Error::InvalidState("CIPSTATUS")));
+    assert!(esp.is_passthrough());
+
+    assert_eq!(esp.exit_passthrough(), Ok(()));
+    assert_eq!(esp.get_status().map(|status| status.status), Ok(3));
+    mock.assert_done();
+}
+
+#[test]
+fn passthrough_needs_connection_of_single_connection_mode() {
+    let mock = Mock::new();
+    let mut esp = driver(&mock);
+
+    assert_eq!(esp.passthrough().err(), Some(Error::InvalidState("CIPMODE")));
+    mock.assert_done();
+}
+
+#[test]
+fn save_trans_link_encodes_tcp_and_udp() {
+    let mock = Mock::new();
+    mock.expect("AT+SAVETRANSLINK=1,\"example.com\",8080,\"TCP\",60")
+        .reply("\r\nOK\r\n");
+    mock.expect("AT+SAVETRANSLINK=1,\"192.168.1.10\",5000,\"UDP\",5001")
+        .reply("\r\nOK\r\n");
+    mock.expect("AT+SAVETRANSLINK=0").reply("\r\nOK\r\n");
+    let mut esp = driver(&mock);
+
+    let tcp = TransLink::Tcp {
+        host: "example.com",
+        port: 8080,
+        keep_alive: 60,
+    };
+    let udp = TransLink::Udp {
+        host: "192.168.1.10",
+        port: 5000,
+        local_port: Some(5001),
+    };
+    assert_eq!(esp.save_trans_link(tcp), Ok(()));
+    assert_eq!(esp.save_trans_link(udp), Ok(()));
+    assert_eq!(esp.clear_trans_link(), Ok(()));
+    mock.assert_done();
+}
+
+#[test]
+fn init_exits_passthrough_the_device_started_in() {
+    let mock = Mock::new();
+    // Written before any command, which would be sent to the remote as data
+    mock.expect_bytes(b"+++");
+    mock.expect("ATE0").reply("ATE0\r\n\r\nOK\r\n");
+    mock.expect("AT+CIPMODE=0").reply("\r\nOK\r\n");
+    mock.expect("AT").reply("\r\nOK\r\n");
+    let mut esp = driver(&mock);
+
+    esp.set_boot_passthrough(BootPassthrough::Exit);
+    assert_eq!(esp.init(), Ok(()));
+    // Guard time and delay around the escape
+    assert_eq!(mock.now(), 1_020);
+    mock.assert_done();
+}
+
+#[test]
+fn init_adopts_passthrough_the_device_started_in() {
+    let mock = Mock::new();
+    mock.expect_bytes(b"+++");
+    mock.expect("ATE0").reply("ATE0\r\n\r\nOK\r\n");
+    mock.expect("AT+CIPSTATUS").reply(
+        "STATUS:3\r\n+CIPSTATUS:0,\"TCP\",\"192.168.1.10\",8080,4321,0\r\n\r\nOK\r\n",
+    );
+    mock.expect("AT").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPMODE=1").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPSEND").reply("\r\nOK\r\n\r\n>");
+    mock.expect_bytes(b"data");
+    let mut esp = driver(&mock);
+
+    esp.set_boot_passthrough(BootPassthrough::Adopt);
+    assert_eq!(esp.init(), Ok(()));
+    let mut stream = esp.passthrough().unwrap();
+    stream.write_all(b"data").unwrap();
+    mock.assert_done();
+}
+
+#[test]
+fn tcp_connect_in_multiple_connections_mode() {
+    let mock = Mock::new();
+    mock.expect("AT+CWMODE=1").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPMUX=1").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPSERVER=1,80").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPSTART=0,\"TCP\",\"192.168.1.10\",8080")
+        .reply("ALREADY CONNECTED\r\n\r\nERROR\r\n");
+    mock.expect("AT+CIPCLOSE=0").reply("0,CLOSED\r\n\r\nOK\r\n");
+    let mut esp = driver(&mock);
+
+    esp.tcp_server(80).unwrap();
+    let socket = esp.tcp_connect("192.168.1.10", 8080).unwrap();
+    assert!(esp.is_connected(&socket));
+    assert_eq!(esp.close_socket(socket), Ok(()));
+    mock.assert_done();
+}
+
+#[test]
+fn commands_too_long_for_the_buffer_are_refused() {
+    let mock = Mock::new();
+    mock.expect("AT+CWLAPOPT=0,31").reply("\r\nOK\r\n");
+    let mut esp = driver(&mock);
+    let host = "a".repeat(120);
+
+    assert_eq!(esp.tcp_connect(&host, 80).err(), Some(Error::InvalidArgument("CIPSTART")));
+    let options = ScanOptions {
+        ssid: Some(&host),
+        ..ScanOptions::default()
+    };
+    assert_eq!(esp.scan(&options), Err(Error::InvalidArgument("CWLAP")));
+    let link = TransLink::Tcp {
+        host: &host,
+        port: 80,
+        keep_alive: 0,
+    };
+    assert_eq!(esp.save_trans_link(link), Err(Error::InvalidArgument("SAVETRANSLINK")));
+    mock.assert_done();
+}
+
+#[test]
+fn tcp_connect_fails_on_error() {
+    let mock = Mock::new();
+    mock.expect("AT+CIPSTART=\"TCP\",\"192.168.1.10\",8080")
+        .reply("ERROR\r\nCLOSED\r\n");
+    let mut esp = driver(&mock);
+    esp.set_retry_policy(RetryPolicy::never());
+
+    assert_eq!(
+        esp.tcp_connect("192.168.1.10", 8080),
+        Err(Error::Error("CIPSTART"))
+    );
+    mock.assert_done();
+}
+
+#[test]
+fn open_socket_fails_without_free_link() {
+    let mock = Mock::new();
+    mock.expect("AT+CIPMUX=1").reply("\r\nOK\r\n");
+    for link_id in 0..5 {
+        mock.expect(&format!("AT+CIPSTART={},\"TCP\",\"192.168.1.10\",80", link_id))
+            .reply(&format!("{},CONNECT\r\n\r\nOK\r\n", link_id));
+    }
+    let mut esp = driver(&mock);
+
+    let sockets: Vec<Socket> = (0..5)
+        .map(|_| esp.open_socket("TCP", "192.168.1.10", 80).unwrap())
+        .collect();
+    assert_eq!(sockets.len(), 5);
+    assert_eq!(
+        esp.open_socket("TCP", "192.168.1.10", 80),
+        Err(Error::NoFreeLink("CIPSTART"))
+    );
+    mock.assert_done();
+}
+
+#[test]
+fn error_is_resent() {
+    let mock = Mock::new();
+    mock.expect("AT+CWMODE=1").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPMUX=1").reply("\r\nERROR\r\n");
+    mock.expect("AT+CIPMUX=1").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPSERVER=1,80").reply("\r\nOK\r\n");
+    let mut esp = driver(&mock);
+
+    assert_eq!(esp.tcp_server(80), Ok(()));
+    mock.assert_done();
+}
+
+#[test]
+fn retries_until_max_attempts() {
+    let mock = Mock::new();
+    mock.expect("AT+CIPSTATUS").reply("\r\nERROR\r\n");
+    mock.expect("AT+CIPSTATUS").reply("busy p...\r\n");
+    mock.expect("AT+CIPSTATUS").reply("\r\nERROR\r\n");
+    let mut esp = driver(&mock);
+    esp.set_retry_policy(RetryPolicy {
+        max_attempts: 3,
+        backoff_ms: 100,
+        max_backoff_ms: 150,
+        ..RetryPolicy::default()
+    });
+
+    assert_eq!(esp.get_status(), Err(Error::Error("CIPSTATUS")));
+    assert_eq!(esp.attempts(), 3);
+    // 100 ms before the second attempt, 150 ms before the third
+    assert!(mock.now() >= 250);
+    mock.assert_done();
+}
+
+#[test]
+fn retry_policy_per_call() {
+    let mock = Mock::new();
+    mock.expect("AT+GMR").reply("busy p...\r\n");
+    let mut esp = driver(&mock);
+
+    assert_eq!(
+        esp.with_retry_policy(RetryPolicy::never(), |esp| esp.get_version()),
+        Err(Error::Busy("GMR"))
+    );
+    assert_eq!(esp.attempts(), 1);
+    mock.assert_done();
+}
+
+#[test]
+fn get_version_decodes_gmr() {
+    let mock = Mock::new();
+    mock.expect("AT+GMR").reply(
+        "AT version:1.7.4.0(May 11 2020 19:13:04)\r\n\
+         SDK version:3.0.4(9532ceb)\r\n\
+         compile time:May 27 2020 10:12:17\r\n\
+         Bin version(Wroom 02):1.7.4\r\n\
+         OK\r\n",
+    );
+    let mut esp = driver(&mock);
+
+    let version = esp.get_version().unwrap();
+    assert_eq!(version.at, "1.7.4.0(May 11 2020 19:13:04)");
+    assert_eq!(version.sdk, "3.0.4(9532ceb)");
+    assert_eq!(version.compile_time, "May 27 2020 10:12:17");
+    mock.assert_done();
+}
+
+#[test]
+fn get_status_decodes_links() {
+    let mock = Mock::new();
+    mock.expect("AT+CIPSTATUS").reply(
+        "STATUS:3\r\n\
+         +CIPSTATUS:0,\"TCP\",\"192.168.1.20\",51234,80,1\r\n\
+         +CIPSTATUS:1,\"UDP\",\"10.0.0.1\",5000,5001,0\r\n\
+         \r\nOK\r\n",
+    );
+    let mut esp = driver(&mock);
+
+    let status = esp.get_status().unwrap();
+    assert_eq!(status.status, 3);
+    assert_eq!(status.links.len(), 2);
+    assert_eq!(status.links[0].link_id, 0);
+    assert_eq!(status.links[0].protocol, "TCP");
+    assert_eq!(status.links[0].remote_ip, Ipv4Addr::new(192, 168, 1, 20));
+    assert_eq!(status.links[0].remote_port, 51234);
+    assert_eq!(status.links[0].local_port, 80);
+    assert!(status.links[0].server);
+    assert_eq!(status.links[1].protocol, "UDP");
+    assert!(!status.links[1].server);
+    mock.assert_done();
+}
+
+#[test]
+fn scan_lists_access_points() {
+    let mock = Mock::new();
+    mock.expect("AT+CWLAPOPT=1,31").reply("\r\nOK\r\n");
+    mock.expect("AT+CWLAP").reply(
+        "+CWLAP:(3,\"home, sweet home\",-45,\"aa:bb:cc:dd:ee:01\",6)\r\n\
+         +CWLAP:(0,\"guest\",-80,\"aa:bb:cc:dd:ee:02\",11)\r\n\
+         \r\nOK\r\n",
+    );
+    let mut esp = driver(&mock);
+
+    let options = ScanOptions {
+        sort_by_rssi: true,
+        ..ScanOptions::default()
+    };
+    let aps = esp.scan(&options).unwrap();
+    assert_eq!(aps.len(), 2);
+    assert_eq!(aps[0].encryption, Some(Encryption::WPA2_PSK));
+    assert_eq!(aps[0].ssid.as_ref().map(|s| s.as_str()), Some("home, sweet home"));
+    assert_eq!(aps[0].rssi, Some(-45));
+    assert_eq!(
+        aps[0].mac,
+        Some(MacAddr::new([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x01]))
+    );
+    assert_eq!(aps[0].channel, Some(6));
+    assert_eq!(aps[1].encryption, Some(Encryption::OPEN));
+    mock.assert_done();
+}
+
+#[test]
+fn scan_with_mask_and_filter() {
+    let mock = Mock::new();
+    mock.expect("AT+CWLAPOPT=0,6").reply("\r\nOK\r\n");
+    mock.expect("AT+CWLAP=\"guest\",,11")
+        .reply("+CWLAP:(\"guest\",-80)\r\n\r\nOK\r\n");
+    let mut esp = driver(&mock);
+
+    let options = ScanOptions {
+        mask: AccessPoint::SSID | AccessPoint::RSSI,
+        ssid: Some("guest"),
+        channel: Some(11),
+        ..ScanOptions::default()
+    };
+    let aps = esp.scan(&options).unwrap();
+    assert_eq!(aps.len(), 1);
+    assert_eq!(aps[0].ssid.as_ref().map(|s| s.as_str()), Some("guest"));
+    assert_eq!(aps[0].rssi, Some(-80));
+    assert_eq!(aps[0].encryption, None);
+    assert_eq!(aps[0].channel, None);
+    mock.assert_done();
+}
+
+#[test]
+fn start_soft_ap_sets_mode_and_configuration() {
+    let mock = Mock::new();
+    mock.expect("AT+CWMODE=3").reply("\r\nOK\r\n");
+    mock.expect("AT+CWSAP_CUR=\"sensor-setup\",\"12345678\",6,3,2,1")
+        .reply("\r\nOK\r\n");
+    let mut esp = driver(&mock);
+
+    esp.start_soft_ap(&SoftApConfig {
+        ssid: "sensor-setup",
+        password: "12345678",
+        channel: 6,
+        max_connections: 2,
+        hidden: true,
+        station: true,
+        ..SoftApConfig::default()
+    })
+    .unwrap();
+    mock.assert_done();
+}
+
+#[test]
+fn start_soft_ap_rejects_invalid_configuration() {
+    let mock = Mock::new();
+    let mut esp = driver(&mock);
+
+    let valid = SoftApConfig {
+        ssid: "sensor-setup",
+        password: "12345678",
+        ..SoftApConfig::default()
+    };
+    let invalid = [
+        SoftApConfig { password: "1234567", ..valid },
+        SoftApConfig { ssid: "", ..valid },
+        SoftApConfig { channel: 14, ..valid },
+        SoftApConfig { max_connections: 5, ..valid },
+        SoftApConfig { encryption: Encryption::WEP, ..valid },
+    ];
+    for config in invalid.iter() {
+        assert_eq!(esp.start_soft_ap(config), Err(Error::InvalidArgument("CWSAP")));
+    }
+    assert!(SoftApConfig { encryption: Encryption::OPEN, password: "", ..valid }.is_valid());
+    assert!(mock.written().is_empty());
+}
+
+#[test]
+fn soft_ap_config_decodes_cwsap() {
+    let mock = Mock::new();
+    mock.expect("AT+CWSAP_CUR?")
+        .reply("+CWSAP_CUR:\"sensor-setup\",\"12345678\",6,3,2,1\r\n\r\nOK\r\n");
+    let mut esp = driver(&mock);
+
+    let info = esp.soft_ap_config().unwrap();
+    assert_eq!(info.ssid.as_str(), "sensor-setup");
+    assert_eq!(info.password.as_str(), "12345678");
+    assert_eq!(info.channel, 6);
+    assert_eq!(info.encryption, Encryption::WPA2_PSK);
+    assert_eq!(info.max_connections, 2);
+    assert!(info.hidden);
+    mock.assert_done();
+}
+
+#[test]
+fn ssid_and_password_are_escaped() {
+    let mock = Mock::new();
+    mock.expect(r#"AT+CWJAP="my \"home\"\,net","p\\ss\,word""#)
+        .reply("WIFI CONNECTED\r\nWIFI GOT IP\r\n\r\nOK\r\n");
+    mock.expect("AT+CIFSR")
+        .reply("+CIFSR:STAIP,\"192.168.1.5\"\r\n\r\nOK\r\n");
+    mock.expect("AT+CWMODE=2").reply("\r\nOK\r\n");
+    mock.expect(r#"AT+CWSAP_CUR="setup\,1","a\"b\\c\,d!",1,3,4,0"#)
+        .reply("\r\nOK\r\n");
+    let mut esp = driver(&mock);
+
+    esp.join_AP("my \"home\",net", "p\\ss,word").unwrap();
+    esp.start_soft_ap(&SoftApConfig {
+        ssid: "setup,1",
+        password: "a\"b\\c,d!",
+        ..SoftApConfig::default()
+    })
+    .unwrap();
+    mock.assert_done();
+}
+
+#[test]
+fn stations_lists_ip_and_mac() {
+    let mock = Mock::new();
+    mock.expect("AT+CWLIF").reply(
+        "192.168.4.2,aa:bb:cc:dd:ee:01\r\n\
+         192.168.4.3,aa:bb:cc:dd:ee:02\r\n\
+         \r\nOK\r\n",
+    );
+    let mut esp = driver(&mock);
+
+    let stations = esp.stations().unwrap();
+    assert_eq!(
+        &stations[..],
+        &[
+            Station {
+                ip: Ipv4Addr::new(192, 168, 4, 2),
+                mac: MacAddr::new([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x01]),
+            },
+            Station {
+                ip: Ipv4Addr::new(192, 168, 4, 3),
+                mac: MacAddr::new([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x02]),
+            },
+        ]
+    );
+    mock.assert_done();
+}
+
+#[test]
+fn station_events_are_kept_across_commands() {
+    let mock = Mock::new();
+    let mac = MacAddr::new([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x01]);
+    mock.expect("AT+CWSAP_CUR?").reply(
+        "+STA_CONNECTED:\"aa:bb:cc:dd:ee:01\"\r\n\
+         +CWSAP_CUR:\"sensor-setup\",\"12345678\",6,3,4,0\r\n\
+         +DIST_STA_IP:\"aa:bb:cc:dd:ee:01\",\"192.168.4.2\"\r\n\
+         \r\nOK\r\n",
+    );
+    let mut esp = driver(&mock);
+
+    assert_eq!(esp.soft_ap_config().unwrap().ssid.as_str(), "sensor-setup");
+    assert_eq!(esp.station_event(), Ok(Some(StationEvent::Connected(mac))));
+    assert_eq!(
+        esp.station_event(),
+        Ok(Some(StationEvent::GotIp(mac, Ipv4Addr::new(192, 168, 4, 2))))
+    );
+
+    mock.reply("+STA_DISCONNECTED:\"aa:bb:cc:dd:ee:01\"\r\n");
+    assert_eq!(esp.station_event(), Ok(Some(StationEvent::Disconnected(mac))));
+    assert_eq!(esp.station_event(), Ok(None));
+    mock.assert_done();
+}
+
+#[test]
+fn set_dhcp_per_interface_and_storage() {
+    let mock = Mock::new();
+    mock.expect("AT+CWDHCP_CUR=2,1").reply("\r\nOK\r\n");
+    mock.expect("AT+CWDHCP_DEF=0,1").reply("\r\nOK\r\n");
+    mock.expect("AT+CWDHCP_DEF=1,0").reply("\r\nOK\r\n");
+    mock.expect("AT+CWDHCP_CUR?").reply("+CWDHCP_CUR:1\r\n\r\nOK\r\n");
+    mock.expect("AT+CWDHCP_DEF?").reply("+CWDHCP_DEF:2\r\n\r\nOK\r\n");
+    let mut esp = driver(&mock);
+
+    let both = Dhcp {
+        station: true,
+        soft_ap: true,
+    };
+    esp.set_dhcp(&both, Storage::Current).unwrap();
+    let server_only = Dhcp {
+        station: false,
+        soft_ap: true,
+    };
+    esp.set_dhcp(&server_only, Storage::Flash).unwrap();
+    assert_eq!(esp.dhcp(Storage::Current), Ok(server_only));
+    let client_only = Dhcp {
+        station: true,
+        soft_ap: false,
+    };
+    assert_eq!(esp.dhcp(Storage::Flash), Ok(client_only));
+    mock.assert_done();
+}
+
+#[test]
+fn set_dhcp_range_sets_and_restores_range() {
+    let mock = Mock::new();
+    mock.expect("AT+CWDHCPS_DEF=1,60,\"192.168.4.10\",\"192.168.4.20\"")
+        .reply("\r\nOK\r\n");
+    mock.expect("AT+CWDHCPS_CUR=0").reply("\r\nOK\r\n");
+    let mut esp = driver(&mock);
+
+    let range = DhcpRange {
+        lease_time: 60,
+        start: Ipv4Addr::new(192, 168, 4, 10),
+        end: Ipv4Addr::new(192, 168, 4, 20),
+    };
+    esp.set_dhcp_range(Some(&range), Storage::Flash).unwrap();
+    esp.set_dhcp_range(None, Storage::Current).unwrap();
+
+    let reversed = DhcpRange {
+        start: range.end,
+        end: range.start,
+        ..range
+    };
+    let too_long = DhcpRange {
+        lease_time: 2881,
+        ..range
+    };
+    for range in [reversed, too_long].iter() {
+        assert_eq!(
+            esp.set_dhcp_range(Some(range), Storage::Current),
+            Err(Error::InvalidArgument("CWDHCPS"))
+        );
+    }
+    mock.assert_done();
+}
+
+#[test]
+fn dhcp_range_decodes_cwdhcps() {
+    let mock = Mock::new();
+    mock.expect("AT+CWDHCPS_DEF?")
+        .reply("+CWDHCPS_DEF:2880,192.168.4.2,192.168.4.101\r\n\r\nOK\r\n");
+    let mut esp = driver(&mock);
+
+    assert_eq!(
+        esp.dhcp_range(Storage::Flash),
+        Ok(DhcpRange {
+            lease_time: 2880,
+            start: Ipv4Addr::new(192, 168, 4, 2),
+            end: Ipv4Addr::new(192, 168, 4, 101),
+        })
+    );
+    mock.assert_done();
+}
@@ -0,0 +1,154 @@
+extern crate ESP8266;
+
+use ESP8266::mock::{Fault, Mock, MockDelay, MockError, MockRx, MockTimer, MockTx};
+use ESP8266::{esp8266, Error, Ipv4Addr, RetryPolicy};
+
+type Esp = esp8266<MockTx, MockRx, MockDelay, MockTimer>;
+
+fn driver(mock: &Mock) -> Esp {
+    let (tx, rx, delay, timer) = mock.split();
+    esp8266::new(tx, rx, delay, timer).unwrap()
+}
+
+#[test]
+fn dropped_byte_times_out_and_next_command_works() {
+    let mock = Mock::new();
+    // "OK" loses its "K"
+    mock.expect("AT+GMR")
+        .reply_with("AT version:1.7.4.0\r\nOK\r\n", &[Fault::Drop(21)]);
+    mock.expect("AT+CIPSTATUS").reply("STATUS:2\r\n\r\nOK\r\n");
+    let mut esp = driver(&mock);
+
+    assert_eq!(esp.get_version(), Err(Error::Timeout("GMR")));
+    assert_eq!(esp.get_status().unwrap().status, 2);
+    mock.assert_done();
+}
+
+#[test]
+fn garbled_data_is_a_parse_error() {
+    let mock = Mock::new();
+    mock.expect("AT+CIFSR")
+        .reply_with("+CIFSR:STAIP,\"192.168.1.5\"\r\nOK\r\n", &[Fault::Garble(17)]);
+    let mut esp = driver(&mock);
+
+    assert_eq!(esp.get_IP(), Err(Error::Parse("CIFSR")));
+    mock.assert_done();
+}
+
+#[test]
+fn reboot_during_command_is_reported() {
+    let mock = Mock::new();
+    mock.expect("AT+CIFSR").reply("+CIFSR:STAIP,\"192.168.1.5\"\r\n\r\nOK\r\n");
+    mock.expect("AT+CIPSTATUS").reboot();
+    let mut esp = driver(&mock);
+
+    esp.get_IP().unwrap();
+    assert_eq!(esp.get_status(), Err(Error::Reset("CIPSTATUS")));
+    assert_eq!(esp.ip(), None);
+    mock.assert_done();
+}
+
+#[test]
+fn reboot_while_waiting_for_data_is_reported() {
+    let mock = Mock::new();
+    mock.delay(100).reboot();
+    let mut esp = driver(&mock);
+
+    let mut buffer = [0u8; 8];
+    assert_eq!(esp.read_network_data(&mut buffer), Err(Error::Reset("IPD")));
+    mock.assert_done();
+}
+
+#[test]
+fn busy_burst_is_resent_once() {
+    let mock = Mock::new();
+    mock.expect("AT+GMR").busy(3);
+    mock.expect("AT+GMR").reply("AT version:1.7.4.0\r\nOK\r\n");
+    let mut esp = driver(&mock);
+
+    let version = esp.get_version().unwrap();
+    assert_eq!(version.at, "1.7.4.0");
+    assert_eq!(esp.attempts(), 2);
+    mock.assert_done();
+}
+
+#[test]
+fn busy_burst_fails_without_retries() {
+    let mock = Mock::new();
+    mock.expect("AT+GMR").busy(3);
+    let mut esp = driver(&mock);
+    esp.set_retry_policy(RetryPolicy::never());
+
+    assert_eq!(esp.get_version(), Err(Error::Busy("GMR")));
+    mock.assert_done();
+}
+
+#[test]
+fn truncated_ipd_times_out_and_next_command_works() {
+    let mock = Mock::new();
+    mock.reply("+IPD,10:abc");
+    mock.expect("AT+CIPSTATUS").reply("STATUS:2\r\n\r\nOK\r\n");
+    let mut esp = driver(&mock);
+
+    let mut buffer = [0u8; 16];
+    assert_eq!(
+        esp.read_network_data(&mut buffer),
+        Err(Error::Timeout("IPD"))
+    );
+    // The rest of the frame would swallow the response of the next command
+    assert_eq!(esp.get_status().unwrap().status, 2);
+    mock.assert_done();
+}
+
+#[test]
+fn urcs_in_the_middle_of_a_response_are_handled() {
+    let mock = Mock::new();
+    mock.expect("AT+CIFSR").reply_with(
+        "+CIFSR:STAIP,\"192.168.1.5\"\r\n+CIFSR:STAMAC,\"18:fe:34:a1:b2:c3\"\r\nOK\r\n",
+        &[
+            Fault::Urc(0, "+IPD,4:ping"),
+            Fault::Urc(28, "WIFI DISCONNECT\r\n"),
+        ],
+    );
+    let mut esp = driver(&mock);
+
+    let info = esp.get_IP().unwrap();
+    assert_eq!(info.station_ip, Some(Ipv4Addr::new(192, 168, 1, 5)));
+    mock.assert_done();
+}
+
+#[test]
+fn serial_error_is_reported() {
+    let mock = Mock::new();
+    mock.expect("AT+GMR").read_error();
+    let mut esp = driver(&mock);
+
+    assert_eq!(esp.get_version(), Err(Error::Read("GMR", MockError)));
+    mock.assert_done();
+}
+
+#[test]
+fn noisy_device_never_hangs() {
+    for seed in 1..200 {
+        let mock = Mock::new();
+        mock.noise(seed, 40);
+        mock.expect("ATE0").reply("ATE0\r\n\r\nOK\r\n");
+        mock.expect("AT").reply("\r\nOK\r\n");
+        mock.expect("AT+CWJAP=\"ssid\",\"password\"")
+            .reply("WIFI CONNECTED\r\nWIFI GOT IP\r\n\r\nOK\r\n");
+        mock.expect("AT+CIFSR").reply(
+            "+CIFSR:STAIP,\"192.168.1.5\"\r\n+CIFSR:STAMAC,\"18:fe:34:a1:b2:c3\"\r\n\r\nOK\r\n",
+        );
+        mock.reply("+IPD,5:hello");
+        let mut esp = driver(&mock);
+        esp.set_retry_policy(RetryPolicy::never());
+
+        let mut buffer = [0u8; 8];
+        let _ = esp
+            .init()
+            .and_then(|_| esp.join_AP("ssid", "password"))
+            .and_then(|_| esp.read_network_data(&mut buffer));
+        // Every step gives up after its timeout at the latest
+        assert!(mock.now() <= 1_000 + 1_000 + 20_000 + 2_000 + 10_000);
+    }
+}
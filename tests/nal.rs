@@ -0,0 +1,203 @@
+extern crate embedded_nal;
+extern crate ESP8266;
+
+use embedded_nal::{nb, IpAddr, Ipv4Addr, SocketAddr, TcpClientStack, TcpFullStack, UdpClientStack};
+use ESP8266::{esp8266, Error};
+use ESP8266::mock::{Mock, MockDelay, MockRx, MockTimer, MockTx};
+
+type Esp = esp8266<MockTx, MockRx, MockDelay, MockTimer>;
+
+fn driver(mock: &Mock) -> Esp {
+    let (tx, rx, delay, timer) = mock.split();
+    esp8266::new(tx, rx, delay, timer).unwrap()
+}
+
+#[test]
+fn tcp_client_stack_round_trip() {
+    let mock = Mock::new();
+    mock.expect("AT+CIPMUX=1").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPSTART=0,\"TCP\",\"192.168.1.10\",1883")
+        .reply("0,CONNECT\r\n\r\nOK\r\n");
+    mock.expect("AT+CIPSEND=0,3").reply("\r\nOK\r\n> ");
+    mock.expect_bytes(&[0x10, 0x00, 0xff])
+        .reply("\r\nRecv 3 bytes\r\n\r\nSEND OK\r\n");
+    mock.reply_bytes(b"+IPD,0,2:\x20\x02");
+    mock.expect("AT+CIPCLOSE=0").reply("0,CLOSED\r\n\r\nOK\r\n");
+    let mut esp = driver(&mock);
+
+    let remote = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10)), 1883);
+    let mut socket = TcpClientStack::socket(&mut esp).unwrap();
+    TcpClientStack::connect(&mut esp, &mut socket, remote).unwrap();
+    assert_eq!(TcpClientStack::is_connected(&mut esp, &socket), Ok(true));
+    assert_eq!(
+        TcpClientStack::send(&mut esp, &mut socket, &[0x10, 0x00, 0xff]),
+        Ok(3)
+    );
+
+    let mut buffer = [0u8; 8];
+    assert_eq!(
+        TcpClientStack::receive(&mut esp, &mut socket, &mut buffer),
+        Ok(2)
+    );
+    assert_eq!(&buffer[..2], &[0x20, 0x02]);
+    assert_eq!(TcpClientStack::close(&mut esp, socket), Ok(()));
+    mock.assert_done();
+}
+
+#[test]
+fn tcp_full_stack_accepts_connections() {
+    let mock = Mock::new();
+    mock.expect("AT+CIPMUX=1").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPSERVER=1,80").reply("\r\nOK\r\n");
+    mock.delay(100).reply("0,CONNECT\r\n");
+    mock.expect("AT+CIPSTATUS").reply(
+        "STATUS:3\r\n+CIPSTATUS:0,\"TCP\",\"192.168.1.20\",50123,80,1\r\n\r\nOK\r\n",
+    );
+    let mut esp = driver(&mock);
+
+    let mut server = TcpClientStack::socket(&mut esp).unwrap();
+    esp.bind(&mut server, 80).unwrap();
+    esp.listen(&mut server).unwrap();
+    assert_eq!(esp.accept(&mut server).err(), Some(nb::Error::WouldBlock));
+    mock.sleep(100);
+    let (connection, remote) = esp.accept(&mut server).unwrap();
+    assert_eq!(
+        remote,
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 20)), 50123)
+    );
+    assert_eq!(TcpClientStack::is_connected(&mut esp, &connection), Ok(true));
+    assert_eq!(esp.accept(&mut server).err(), Some(nb::Error::WouldBlock));
+    mock.assert_done();
+}
+
+#[test]
+fn receive_does_not_wait_for_data() {
+    let mock = Mock::new();
+    mock.expect("AT+CIPMUX=1").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPSTART=0,\"TCP\",\"192.168.1.10\",1883")
+        .reply("0,CONNECT\r\n\r\nOK\r\n");
+    mock.delay(50).reply("+IPD,0,2:hi");
+    let mut esp = driver(&mock);
+
+    let remote = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10)), 1883);
+    let mut socket = TcpClientStack::socket(&mut esp).unwrap();
+    TcpClientStack::connect(&mut esp, &mut socket, remote).unwrap();
+    let mut buffer = [0u8; 8];
+    let now = mock.now();
+    assert_eq!(
+        TcpClientStack::receive(&mut esp, &mut socket, &mut buffer),
+        Err(nb::Error::WouldBlock)
+    );
+    assert_eq!(mock.now(), now);
+
+    mock.sleep(50);
+    assert_eq!(
+        TcpClientStack::receive(&mut esp, &mut socket, &mut buffer),
+        Ok(2)
+    );
+    assert_eq!(&buffer[..2], b"hi");
+    mock.assert_done();
+}
+
+#[test]
+fn udp_receive_returns_one_datagram() {
+    let mock = Mock::new();
+    mock.expect("AT+CIPMUX=1").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPSTART=0,\"UDP\",\"192.168.1.10\",5000")
+        .reply("0,CONNECT\r\n\r\nOK\r\n");
+    mock.reply("+IPD,0,5:first+IPD,0,0:+IPD,0,6:second");
+    let mut esp = driver(&mock);
+
+    let remote = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10)), 5000);
+    let mut socket = UdpClientStack::socket(&mut esp).unwrap();
+    UdpClientStack::connect(&mut esp, &mut socket, remote).unwrap();
+
+    let mut buffer = [0u8; 8];
+    assert_eq!(
+        UdpClientStack::receive(&mut esp, &mut socket, &mut buffer),
+        Ok((5, remote))
+    );
+    assert_eq!(&buffer[..5], b"first");
+    assert_eq!(
+        UdpClientStack::receive(&mut esp, &mut socket, &mut buffer),
+        Ok((0, remote))
+    );
+    // The end of the datagram does not fit and is dropped
+    let mut buffer = [0u8; 3];
+    assert_eq!(
+        UdpClientStack::receive(&mut esp, &mut socket, &mut buffer),
+        Ok((3, remote))
+    );
+    assert_eq!(&buffer, b"sec");
+    assert_eq!(
+        UdpClientStack::receive(&mut esp, &mut socket, &mut buffer),
+        Err(nb::Error::WouldBlock)
+    );
+    mock.assert_done();
+}
+
+#[test]
+fn udp_datagrams_beyond_the_queue_are_dropped() {
+    let mock = Mock::new();
+    mock.expect("AT+CIPMUX=1").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPSTART=0,\"UDP\",\"192.168.1.10\",5000")
+        .reply("0,CONNECT\r\n\r\nOK\r\n");
+    for _ in 0..9 {
+        mock.reply("+IPD,0,1:x");
+    }
+    let mut esp = driver(&mock);
+
+    let remote = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10)), 5000);
+    let mut socket = UdpClientStack::socket(&mut esp).unwrap();
+    UdpClientStack::connect(&mut esp, &mut socket, remote).unwrap();
+
+    let mut buffer = [0u8; 8];
+    assert_eq!(
+        UdpClientStack::receive(&mut esp, &mut socket, &mut buffer),
+        Err(nb::Error::Other(Error::BufferOverflow("IPD")))
+    );
+    for _ in 0..8 {
+        assert_eq!(
+            UdpClientStack::receive(&mut esp, &mut socket, &mut buffer),
+            Ok((1, remote))
+        );
+    }
+    assert_eq!(
+        UdpClientStack::receive(&mut esp, &mut socket, &mut buffer),
+        Err(nb::Error::WouldBlock)
+    );
+    mock.assert_done();
+}
+
+#[test]
+fn passive_receive_pulls_data() {
+    let mock = Mock::new();
+    mock.expect("AT+CIPRECVMODE=1").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPMUX=1").reply("\r\nOK\r\n");
+    mock.expect("AT+CIPSTART=0,\"TCP\",\"192.168.1.10\",80")
+        .reply("0,CONNECT\r\n\r\nOK\r\n");
+    mock.expect("AT+CIPRECVLEN?")
+        .reply("+CIPRECVLEN:0,-1,-1,-1,-1\r\n\r\nOK\r\n");
+    mock.expect("AT+CIPRECVLEN?")
+        .reply("+CIPRECVLEN:3,-1,-1,-1,-1\r\n\r\nOK\r\n");
+    mock.expect("AT+CIPRECVDATA=0,3")
+        .reply("+CIPRECVDATA:3,abc\r\nOK\r\n");
+    let mut esp = driver(&mock);
+
+    esp.set_passive_receive(true).unwrap();
+    let remote = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10)), 80);
+    let mut socket = TcpClientStack::socket(&mut esp).unwrap();
+    TcpClientStack::connect(&mut esp, &mut socket, remote).unwrap();
+
+    let mut buffer = [0u8; 8];
+    assert_eq!(
+        TcpClientStack::receive(&mut esp, &mut socket, &mut buffer),
+        Err(nb::Error::WouldBlock)
+    );
+    assert_eq!(
+        TcpClientStack::receive(&mut esp, &mut socket, &mut buffer),
+        Ok(3)
+    );
+    assert_eq!(&buffer[..3], b"abc");
+    mock.assert_done();
+}